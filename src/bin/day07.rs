@@ -1,9 +1,11 @@
 use std::collections::{HashMap, HashSet};
 use std::convert::From;
 
+use num_bigint::BigUint;
+use num_traits::{One, Zero};
 use regex::Regex;
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 struct BagType {
     color: String,
 }
@@ -50,6 +52,38 @@ impl std::str::FromStr for BagRule {
     }
 }
 
+// A chain of bags, each directly containing the next.  The counts
+// are the number of each bag held by the bag before it.
+#[derive(Debug, Clone)]
+struct BagPath {
+    bags: Vec<BagType>,
+    counts: Vec<i32>,
+}
+
+impl BagPath {
+    // The number of innermost bags contributed by this path.
+    fn multiplicity(&self) -> BigUint {
+        self.counts
+            .iter()
+            .map(|&num| BigUint::from(num as u32))
+            .product()
+    }
+
+    fn depth(&self) -> usize {
+        self.counts.len()
+    }
+}
+
+impl std::fmt::Display for BagPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.bags[0].color)?;
+        for (num, bag) in self.counts.iter().zip(self.bags.iter().skip(1)) {
+            write!(f, " -({})-> {}", num, bag.color)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 struct BagGraph {
     contains: HashMap<BagType, Vec<(i32, BagType)>>,
@@ -102,7 +136,27 @@ impl BagGraph {
             let new_colors = self.is_contained_by.get(&color);
             if let Some(new_colors) = new_colors {
                 for new_color in new_colors {
-                    unchecked.push(new_color.clone());
+                    if !output.contains(new_color) {
+                        unchecked.push(new_color.clone());
+                    }
+                }
+            }
+            output.insert(color);
+        }
+
+        output
+    }
+
+    fn reachable_from(&self, base: BagType) -> HashSet<BagType> {
+        let mut output = HashSet::<BagType>::new();
+        let mut unchecked = vec![base];
+
+        while let Some(color) = unchecked.pop() {
+            if let Some(contents) = self.contains.get(&color) {
+                for (_num, inner_bag) in contents {
+                    if !output.contains(inner_bag) {
+                        unchecked.push(inner_bag.clone());
+                    }
                 }
             }
             output.insert(color);
@@ -111,19 +165,388 @@ impl BagGraph {
         output
     }
 
-    fn num_contained(&self, base: &BagType) -> i32 {
-        match self.contains.get(&base) {
-            None => 0,
-            Some(contents) => contents
-                .iter()
-                .map(|(num, inner_bag)| {
-                    num * (self.num_contained(inner_bag) + 1)
+    // Restricts the graph to the given bags, dropping any rule that
+    // refers to a bag outside of the set.
+    fn subgraph(&self, bags: &HashSet<BagType>) -> BagGraph {
+        let contains = self
+            .contains
+            .iter()
+            .filter(|(bag, _contents)| bags.contains(bag))
+            .map(|(bag, contents)| {
+                (
+                    bag.clone(),
+                    contents
+                        .iter()
+                        .filter(|(_num, inner_bag)| bags.contains(inner_bag))
+                        .cloned()
+                        .collect(),
+                )
+            })
+            .collect();
+
+        let is_contained_by = self
+            .is_contained_by
+            .iter()
+            .filter(|(bag, _containers)| bags.contains(bag))
+            .map(|(bag, containers)| {
+                (
+                    bag.clone(),
+                    containers
+                        .iter()
+                        .filter(|container| bags.contains(container))
+                        .cloned()
+                        .collect(),
+                )
+            })
+            .collect();
+
+        BagGraph {
+            contains,
+            is_contained_by,
+        }
+    }
+
+    fn sorted_contains(&self) -> Vec<(&BagType, Vec<&(i32, BagType)>)> {
+        let mut output = self
+            .contains
+            .iter()
+            .map(|(bag, contents)| {
+                let mut contents = contents.iter().collect::<Vec<_>>();
+                contents.sort_by(|a, b| a.1.cmp(&b.1));
+                (bag, contents)
+            })
+            .collect::<Vec<_>>();
+        output.sort_by(|a, b| a.0.cmp(b.0));
+        output
+    }
+
+    fn to_dot(&self) -> String {
+        let mut output = "digraph bags {\n".to_string();
+        for (bag, contents) in self.sorted_contains() {
+            output += &format!("    \"{}\";\n", bag.color);
+            for (num, inner_bag) in contents {
+                output += &format!(
+                    "    \"{}\" -> \"{}\" [label=\"{}\"];\n",
+                    bag.color, inner_bag.color, num
+                );
+            }
+        }
+        output += "}\n";
+        output
+    }
+
+    fn to_json(&self) -> String {
+        let contains = self
+            .sorted_contains()
+            .into_iter()
+            .map(|(bag, contents)| {
+                let contents = contents
+                    .iter()
+                    .map(|(num, inner_bag)| {
+                        format!(
+                            "{{\"count\": {}, \"color\": {}}}",
+                            num,
+                            json_string(&inner_bag.color)
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                format!(
+                    "    {}: [{}]",
+                    json_string(&bag.color),
+                    contents.join(", ")
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut is_contained_by =
+            self.is_contained_by.iter().collect::<Vec<_>>();
+        is_contained_by.sort_by(|a, b| a.0.cmp(b.0));
+        let is_contained_by = is_contained_by
+            .into_iter()
+            .map(|(bag, containers)| {
+                let mut containers = containers
+                    .iter()
+                    .map(|container| json_string(&container.color))
+                    .collect::<Vec<_>>();
+                containers.sort();
+                format!(
+                    "    {}: [{}]",
+                    json_string(&bag.color),
+                    containers.join(", ")
+                )
+            })
+            .collect::<Vec<_>>();
+
+        format!(
+            "{{\n  \"contains\": {{\n{}\n  }},\n  \"is_contained_by\": {{\n{}\n  }}\n}}\n",
+            contains.join(",\n"),
+            is_contained_by.join(",\n")
+        )
+    }
+
+    // Bags that are never contained by any other bag.
+    fn roots(&self) -> Vec<BagType> {
+        let mut output = self
+            .contains
+            .keys()
+            .filter(|bag| {
+                self.is_contained_by
+                    .get(bag)
+                    .is_none_or(|containers| containers.is_empty())
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        output.sort();
+        output
+    }
+
+    // Bags that do not contain any other bag.
+    fn leaves(&self) -> Vec<BagType> {
+        let mut output = self
+            .contains
+            .iter()
+            .filter(|(_bag, contents)| contents.is_empty())
+            .map(|(bag, _contents)| bag.clone())
+            .collect::<Vec<_>>();
+        output.sort();
+        output
+    }
+
+    fn all_paths(
+        &self,
+        from: &BagType,
+        to: &BagType,
+    ) -> Result<Vec<BagPath>, util::Error> {
+        // A cycle makes the number of bags unbounded, as it does for
+        // shortest_path and deepest_path.
+        self.topological_order(from)?;
+
+        // Only bags that can eventually hold the target are worth
+        // descending into.
+        let can_reach = self.indirectly_contains(to.clone());
+        if !can_reach.contains(from) {
+            return Ok(Vec::new());
+        }
+
+        let mut output = Vec::new();
+        let mut current = BagPath {
+            bags: vec![from.clone()],
+            counts: Vec::new(),
+        };
+        self.extend_paths(to, &can_reach, &mut current, &mut output);
+        Ok(output)
+    }
+
+    fn extend_paths(
+        &self,
+        to: &BagType,
+        can_reach: &HashSet<BagType>,
+        current: &mut BagPath,
+        output: &mut Vec<BagPath>,
+    ) {
+        let bag = current.bags.last().unwrap().clone();
+        if &bag == to {
+            output.push(current.clone());
+            return;
+        }
+
+        let contents = match self.contains.get(&bag) {
+            Some(contents) => contents,
+            None => return,
+        };
+        for (num, inner_bag) in contents {
+            if can_reach.contains(inner_bag)
+                && !current.bags.contains(inner_bag)
+            {
+                current.bags.push(inner_bag.clone());
+                current.counts.push(*num);
+                self.extend_paths(to, can_reach, current, output);
+                current.bags.pop();
+                current.counts.pop();
+            }
+        }
+    }
+
+    fn shortest_path(
+        &self,
+        from: &BagType,
+        to: &BagType,
+    ) -> Result<Option<BagPath>, util::Error> {
+        self.extreme_path(from, to, |new, old| new < old)
+    }
+
+    fn deepest_path(
+        &self,
+        from: &BagType,
+        to: &BagType,
+    ) -> Result<Option<BagPath>, util::Error> {
+        self.extreme_path(from, to, |new, old| new > old)
+    }
+
+    // Finds the path from one bag to another whose depth is preferred
+    // over all others, without enumerating every path.
+    fn extreme_path(
+        &self,
+        from: &BagType,
+        to: &BagType,
+        is_better: fn(usize, usize) -> bool,
+    ) -> Result<Option<BagPath>, util::Error> {
+        // For each bag, the best path from that bag to the target,
+        // built up from the innermost bags outward.
+        let mut best = HashMap::<BagType, Option<BagPath>>::new();
+        for bag in self.topological_order(from)?.into_iter().rev() {
+            let path = if &bag == to {
+                Some(BagPath {
+                    bags: vec![bag.clone()],
+                    counts: Vec::new(),
+                })
+            } else {
+                self.contains
+                    .get(&bag)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|(num, inner_bag)| {
+                        best[inner_bag].as_ref().map(|inner_path| {
+                            let mut bags = vec![bag.clone()];
+                            bags.extend(inner_path.bags.iter().cloned());
+                            let mut counts = vec![*num];
+                            counts.extend(inner_path.counts.iter());
+                            BagPath { bags, counts }
+                        })
+                    })
+                    .fold(None, |best_path: Option<BagPath>, path| {
+                        match best_path {
+                            Some(best_path)
+                                if !is_better(
+                                    path.depth(),
+                                    best_path.depth(),
+                                ) =>
+                            {
+                                Some(best_path)
+                            }
+                            _ => Some(path),
+                        }
+                    })
+            };
+            best.insert(bag, path);
+        }
+
+        Ok(best.remove(from).flatten())
+    }
+
+    // Returns every bag reachable from base (including base), ordered
+    // so that each container appears before all of its contents.
+    fn topological_order(
+        &self,
+        base: &BagType,
+    ) -> Result<Vec<BagType>, util::Error> {
+        let mut finished = HashSet::<BagType>::new();
+        let mut in_progress = HashSet::<BagType>::new();
+        let mut output = Vec::new();
+
+        // Depth-first search, with the bool marking whether the
+        // contents of the bag have already been pushed.
+        let mut stack = vec![(base.clone(), false)];
+        while let Some((bag, contents_visited)) = stack.pop() {
+            if contents_visited {
+                in_progress.remove(&bag);
+                finished.insert(bag.clone());
+                output.push(bag);
+                continue;
+            }
+
+            if finished.contains(&bag) {
+                continue;
+            }
+            if !in_progress.insert(bag.clone()) {
+                return Err(util::Error::InvalidValue(format!(
+                    "Cycle through {} bag",
+                    bag.color
+                )));
+            }
+
+            stack.push((bag.clone(), true));
+            if let Some(contents) = self.contains.get(&bag) {
+                for (_num, inner_bag) in contents {
+                    if in_progress.contains(inner_bag) {
+                        return Err(util::Error::InvalidValue(format!(
+                            "Cycle through {} bag",
+                            inner_bag.color
+                        )));
+                    }
+                    if !finished.contains(inner_bag) {
+                        stack.push((inner_bag.clone(), false));
+                    }
+                }
+            }
+        }
+
+        output.reverse();
+        Ok(output)
+    }
+
+    fn num_contained(&self, base: &BagType) -> Result<BigUint, util::Error> {
+        // Walk from the innermost bags outward, so that each bag's
+        // total is computed exactly once.
+        let mut totals = HashMap::<BagType, BigUint>::new();
+        for bag in self.topological_order(base)?.into_iter().rev() {
+            let total = self
+                .contains
+                .get(&bag)
+                .map(|contents| {
+                    contents
+                        .iter()
+                        .map(|(num, inner_bag)| {
+                            (&totals[inner_bag] + BigUint::one())
+                                * BigUint::from(*num as u32)
+                        })
+                        .sum()
                 })
-                .sum(),
+                .unwrap_or_else(BigUint::zero);
+            totals.insert(bag, total);
         }
+
+        Ok(totals.remove(base).unwrap())
+    }
+
+    fn contents_breakdown(
+        &self,
+        base: &BagType,
+    ) -> Result<HashMap<BagType, BigUint>, util::Error> {
+        // Walk from the outermost bag inward, so that every container
+        // of a bag has been counted before its contents are.
+        let mut counts = HashMap::<BagType, BigUint>::new();
+        counts.insert(base.clone(), BigUint::one());
+        for bag in self.topological_order(base)? {
+            let num_bag = counts[&bag].clone();
+            if let Some(contents) = self.contains.get(&bag) {
+                for (num, inner_bag) in contents {
+                    *counts
+                        .entry(inner_bag.clone())
+                        .or_insert_with(BigUint::zero) +=
+                        &num_bag * BigUint::from(*num as u32);
+                }
+            }
+        }
+
+        counts.remove(base);
+        Ok(counts)
     }
 }
 
+fn json_string(s: &str) -> String {
+    let escaped = s
+        .chars()
+        .map(|c| match c {
+            '"' => "\\\"".to_string(),
+            '\\' => "\\\\".to_string(),
+            c if c.is_control() => format!("\\u{:04x}", c as u32),
+            c => c.to_string(),
+        })
+        .collect::<String>();
+    format!("\"{}\"", escaped)
+}
+
 fn main() -> Result<(), util::Error> {
     let args: Vec<String> = std::env::args().collect();
     let filename = &args[1];
@@ -142,6 +565,72 @@ fn main() -> Result<(), util::Error> {
     let target = BagType {
         color: "shiny gold".to_string(),
     };
+
+    let export_graph =
+        match (util::arg_value(&args, "from"), util::arg_value(&args, "to")) {
+            (Some(_), Some(_)) => {
+                return Err(util::Error::InvalidValue(
+                    "Only one of --from and --to may be given".to_string(),
+                ))
+            }
+            (Some(color), None) => {
+                graph.subgraph(&graph.reachable_from(BagType {
+                    color: color.to_string(),
+                }))
+            }
+            (None, Some(color)) => {
+                graph.subgraph(&graph.indirectly_contains(BagType {
+                    color: color.to_string(),
+                }))
+            }
+            (None, None) => {
+                graph.subgraph(&graph.contains.keys().cloned().collect())
+            }
+        };
+    if let Some(dot_file) = util::arg_value(&args, "dot") {
+        std::fs::write(dot_file, export_graph.to_dot())?;
+    }
+    if let Some(json_file) = util::arg_value(&args, "json") {
+        std::fs::write(json_file, export_graph.to_json())?;
+    }
+
+    if util::has_flag(&args, "ends") {
+        println!("Root bags:");
+        graph
+            .roots()
+            .iter()
+            .for_each(|bag| println!("\t{}", bag.color));
+        println!("Leaf bags:");
+        graph
+            .leaves()
+            .iter()
+            .for_each(|bag| println!("\t{}", bag.color));
+    }
+
+    if let (Some(from), Some(to)) = (
+        util::arg_value(&args, "path-from"),
+        util::arg_value(&args, "path-to"),
+    ) {
+        let from = BagType {
+            color: from.to_string(),
+        };
+        let to = BagType {
+            color: to.to_string(),
+        };
+
+        let paths = graph.all_paths(&from, &to)?;
+        println!("Paths from {} to {}: {}", from.color, to.color, paths.len());
+        paths.iter().for_each(|path| {
+            println!("\t{} (x{})", path, path.multiplicity());
+        });
+        if let Some(path) = graph.shortest_path(&from, &to)? {
+            println!("Shortest path: {} (x{})", path, path.multiplicity());
+        }
+        if let Some(path) = graph.deepest_path(&from, &to)? {
+            println!("Deepest path: {} (x{})", path, path.multiplicity());
+        }
+    }
+
     let indirectly_contains = graph.indirectly_contains(target.clone());
 
     println!(
@@ -150,8 +639,20 @@ fn main() -> Result<(), util::Error> {
     );
     println!(
         "Minimum bags in shiny gold: {}",
-        graph.num_contained(&target)
+        graph.num_contained(&target)?
     );
 
+    let mut breakdown = graph
+        .contents_breakdown(&target)?
+        .into_iter()
+        .collect::<Vec<_>>();
+    breakdown.sort_by(|(a_bag, a_num), (b_bag, b_num)| {
+        b_num.cmp(a_num).then_with(|| a_bag.color.cmp(&b_bag.color))
+    });
+    println!("Bags required inside shiny gold:");
+    breakdown.iter().for_each(|(bag, num)| {
+        println!("\t{} {}", num, bag.color);
+    });
+
     Ok(())
 }
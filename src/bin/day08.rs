@@ -1,26 +1,279 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::convert::From;
+use std::io::{BufRead, Write};
 
-#[derive(Debug, Clone)]
+const NUM_REGISTERS: usize = 26;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Register {
+    Acc,
+    // General-purpose registers, named a through z.
+    Named(usize),
+}
+
+impl std::str::FromStr for Register {
+    type Err = util::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = s.as_bytes();
+        if s == "acc" {
+            Ok(Register::Acc)
+        } else if bytes.len() == 1 && bytes[0].is_ascii_lowercase() {
+            Ok(Register::Named((bytes[0] - b'a') as usize))
+        } else {
+            Err(util::Error::InvalidValue(s.to_owned()))
+        }
+    }
+}
+
+impl std::fmt::Display for Register {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Register::Acc => write!(f, "acc"),
+            Register::Named(i) => write!(f, "{}", (b'a' + *i as u8) as char),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operand {
+    Value(i32),
+    Register(Register),
+}
+
+impl std::str::FromStr for Operand {
+    type Err = util::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<i32>() {
+            Ok(val) => Ok(Operand::Value(val)),
+            Err(_) => Ok(Operand::Register(s.parse()?)),
+        }
+    }
+}
+
+impl std::fmt::Display for Operand {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Operand::Value(val) => write!(f, "{}", val),
+            Operand::Register(reg) => write!(f, "{}", reg),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 enum Instruction {
     Acc(i32),
     Jmp(i32),
     Nop(i32),
+    Set(Register, Operand),
+    Add(Register, Operand),
+    Mul(Register, Operand),
+    // Jumps by the offset if the register is zero.
+    Jz(Register, i32),
+    // Jumps by the offset if the register is non-zero.
+    Jnz(Register, i32),
+    Out(Operand),
+    Halt,
+}
+
+impl Instruction {
+    // Parses a single instruction at the given ip, resolving any jump
+    // target that names a label into a relative offset.
+    fn parse(
+        s: &str,
+        ip: usize,
+        labels: &HashMap<String, usize>,
+    ) -> Result<Self, util::Error> {
+        let words = s.split_whitespace().collect::<Vec<_>>();
+        let command = *words.first().ok_or(util::Error::ParseError)?;
+        let num_args = match command {
+            "halt" => 0,
+            "acc" | "jmp" | "nop" | "out" => 1,
+            "set" | "add" | "mul" | "jz" | "jnz" => 2,
+            _ => return Err(util::Error::InvalidValue(command.to_owned())),
+        };
+        if words.len() != num_args + 1 {
+            return Err(util::Error::InvalidValue(s.to_owned()));
+        }
+
+        let offset = |word: &str| -> Result<i32, util::Error> {
+            match word.parse::<i32>() {
+                Ok(val) => Ok(val),
+                Err(_) => labels
+                    .get(word)
+                    .map(|&target| target as i32 - ip as i32)
+                    .ok_or_else(|| util::Error::InvalidValue(word.to_owned())),
+            }
+        };
+
+        Ok(match command {
+            "acc" => Instruction::Acc(words[1].parse()?),
+            "jmp" => Instruction::Jmp(offset(words[1])?),
+            "nop" => Instruction::Nop(offset(words[1])?),
+            "set" => Instruction::Set(words[1].parse()?, words[2].parse()?),
+            "add" => Instruction::Add(words[1].parse()?, words[2].parse()?),
+            "mul" => Instruction::Mul(words[1].parse()?, words[2].parse()?),
+            "jz" => Instruction::Jz(words[1].parse()?, offset(words[2])?),
+            "jnz" => Instruction::Jnz(words[1].parse()?, offset(words[2])?),
+            "out" => Instruction::Out(words[1].parse()?),
+            _ => Instruction::Halt,
+        })
+    }
+
+    // The relative jump offset, for instructions that may jump.
+    fn jump_offset(&self) -> Option<i32> {
+        match self {
+            Instruction::Jmp(val)
+            | Instruction::Jz(_, val)
+            | Instruction::Jnz(_, val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    // Formats the instruction with its jump offset replaced by a label.
+    fn with_label(&self, label: &str) -> String {
+        match self {
+            Instruction::Jmp(_) => format!("jmp {}", label),
+            Instruction::Jz(reg, _) => format!("jz {} {}", reg, label),
+            Instruction::Jnz(reg, _) => format!("jnz {} {}", reg, label),
+            _ => self.to_string(),
+        }
+    }
 }
 
 impl std::str::FromStr for Instruction {
     type Err = util::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let word_iter: Vec<_> = s.split(' ').collect();
-        let command = word_iter[0];
-        let argument = word_iter[1].parse::<i32>()?;
+        Instruction::parse(s, 0, &HashMap::new())
+    }
+}
+
+impl std::fmt::Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Instruction::Acc(val) => write!(f, "acc {:+}", val),
+            Instruction::Jmp(val) => write!(f, "jmp {:+}", val),
+            Instruction::Nop(val) => write!(f, "nop {:+}", val),
+            Instruction::Set(reg, op) => write!(f, "set {} {}", reg, op),
+            Instruction::Add(reg, op) => write!(f, "add {} {}", reg, op),
+            Instruction::Mul(reg, op) => write!(f, "mul {} {}", reg, op),
+            Instruction::Jz(reg, val) => write!(f, "jz {} {:+}", reg, val),
+            Instruction::Jnz(reg, val) => write!(f, "jnz {} {:+}", reg, val),
+            Instruction::Out(op) => write!(f, "out {}", op),
+            Instruction::Halt => write!(f, "halt"),
+        }
+    }
+}
+
+// Converts assembly source into a program.  Each line holds an
+// instruction, optionally preceded by "label:", and anything after a
+// ';' is a comment.  Jump targets may be either relative offsets or
+// labels.
+fn assemble<S: AsRef<str>>(
+    lines: &[S],
+) -> Result<Vec<Instruction>, util::Error> {
+    let statements = lines
+        .iter()
+        .map(|line| {
+            let line = line.as_ref();
+            let line = line.split(';').next().unwrap();
+            match line.find(':') {
+                Some(pos) => (Some(line[..pos].trim()), line[pos + 1..].trim()),
+                None => (None, line.trim()),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut labels = HashMap::<String, usize>::new();
+    let mut ip = 0;
+    for (label, instruction) in &statements {
+        if let Some(label) = label {
+            if labels.insert(label.to_string(), ip).is_some() {
+                return Err(util::Error::InvalidValue(label.to_string()));
+            }
+        }
+        if !instruction.is_empty() {
+            ip += 1;
+        }
+    }
+
+    statements
+        .iter()
+        .filter(|(_label, instruction)| !instruction.is_empty())
+        .enumerate()
+        .map(|(ip, (_label, instruction))| {
+            Instruction::parse(instruction, ip, &labels)
+        })
+        .collect()
+}
+
+// Converts a program back into assembly, replacing every in-range
+// jump offset with a generated label.
+fn disassemble(program: &[Instruction]) -> String {
+    let targets = program
+        .iter()
+        .enumerate()
+        .filter_map(|(ip, instruction)| {
+            instruction
+                .jump_offset()
+                .map(|offset| ip as i64 + offset as i64)
+        })
+        .filter(|&target| target >= 0 && target as usize <= program.len())
+        .map(|target| target as usize)
+        .collect::<HashSet<_>>();
+    let mut targets = targets.into_iter().collect::<Vec<_>>();
+    targets.sort_unstable();
+    let labels = targets
+        .iter()
+        .enumerate()
+        .map(|(i, &target)| (target, format!("L{}", i)))
+        .collect::<HashMap<_, _>>();
 
-        match command {
-            "acc" => Ok(Instruction::Acc(argument)),
-            "jmp" => Ok(Instruction::Jmp(argument)),
-            "nop" => Ok(Instruction::Nop(argument)),
-            _ => Err(util::Error::InvalidValue(command.to_owned())),
+    let mut output = String::new();
+    for ip in 0..=program.len() {
+        if let Some(label) = labels.get(&ip) {
+            output += &format!("{}:\n", label);
+        }
+        let instruction = match program.get(ip) {
+            Some(instruction) => instruction,
+            None => break,
+        };
+        let target = instruction
+            .jump_offset()
+            .map(|offset| ip as i64 + offset as i64)
+            .filter(|&target| target >= 0)
+            .and_then(|target| labels.get(&(target as usize)));
+        let text = match target {
+            Some(label) => instruction.with_label(label),
+            None => instruction.to_string(),
+        };
+        output += &format!("    {}\n", text);
+    }
+    output
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Fault {
+    // The instruction at ip would move the instruction pointer to
+    // target, which is outside the program and not exactly one past the
+    // final instruction.
+    OutOfBounds { ip: i32, target: i64 },
+    Overflow(i32),
+}
+
+impl std::fmt::Display for Fault {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Fault::OutOfBounds { ip, target } => {
+                write!(
+                    f,
+                    "jump from ip = {} to {} leaves the program",
+                    ip, target
+                )
+            }
+            Fault::Overflow(ip) => write!(f, "overflow at ip = {}", ip),
         }
     }
 }
@@ -30,6 +283,9 @@ struct VirtualMachine {
     program: Vec<Instruction>,
     ip: i32,
     acc: i32,
+    registers: [i32; NUM_REGISTERS],
+    output: Vec<i32>,
+    halted: bool,
 }
 
 impl From<Vec<Instruction>> for VirtualMachine {
@@ -38,58 +294,624 @@ impl From<Vec<Instruction>> for VirtualMachine {
             program,
             ip: 0,
             acc: 0,
+            registers: [0; NUM_REGISTERS],
+            output: Vec::new(),
+            halted: false,
         }
     }
 }
 
 impl VirtualMachine {
-    fn iter(&mut self) {
+    fn register(&self, reg: Register) -> i32 {
+        match reg {
+            Register::Acc => self.acc,
+            Register::Named(i) => self.registers[i],
+        }
+    }
+
+    fn register_mut(&mut self, reg: Register) -> &mut i32 {
+        match reg {
+            Register::Acc => &mut self.acc,
+            Register::Named(i) => &mut self.registers[i],
+        }
+    }
+
+    fn value(&self, op: Operand) -> i32 {
+        match op {
+            Operand::Value(val) => val,
+            Operand::Register(reg) => self.register(reg),
+        }
+    }
+
+    fn update(
+        &mut self,
+        reg: Register,
+        op: Operand,
+        func: fn(i32, i32) -> Option<i32>,
+    ) -> Result<(), Fault> {
+        let val = self.value(op);
+        let ip = self.ip;
+        let target = self.register_mut(reg);
+        *target = func(*target, val).ok_or(Fault::Overflow(ip))?;
+        Ok(())
+    }
+
+    fn iter(&mut self) -> Result<(), Fault> {
+        if self.ip < 0 || self.ip as usize >= self.program.len() {
+            return Err(Fault::OutOfBounds {
+                ip: self.ip,
+                target: self.ip as i64,
+            });
+        }
+
+        let mut jump = 1;
         match self.program[self.ip as usize] {
             Instruction::Acc(val) => {
-                self.acc += val;
-                self.ip += 1;
+                self.update(
+                    Register::Acc,
+                    Operand::Value(val),
+                    i32::checked_add,
+                )?;
             }
             Instruction::Jmp(val) => {
-                self.ip += val;
+                jump = val;
+            }
+            Instruction::Nop(_) => {}
+            Instruction::Set(reg, op) => {
+                *self.register_mut(reg) = self.value(op);
+            }
+            Instruction::Add(reg, op) => {
+                self.update(reg, op, i32::checked_add)?;
+            }
+            Instruction::Mul(reg, op) => {
+                self.update(reg, op, i32::checked_mul)?;
+            }
+            Instruction::Jz(reg, val) => {
+                if self.register(reg) == 0 {
+                    jump = val;
+                }
             }
-            Instruction::Nop(_) => {
-                self.ip += 1;
+            Instruction::Jnz(reg, val) => {
+                if self.register(reg) != 0 {
+                    jump = val;
+                }
             }
+            Instruction::Out(op) => {
+                self.output.push(self.value(op));
+            }
+            Instruction::Halt => {
+                self.halted = true;
+                jump = 0;
+            }
+        }
+
+        let target = self.ip as i64 + jump as i64;
+        if target < 0 || target > self.program.len() as i64 {
+            return Err(Fault::OutOfBounds {
+                ip: self.ip,
+                target,
+            });
         }
+        self.ip = target as i32;
+        Ok(())
     }
 
     fn terminated(&self) -> bool {
-        (self.ip as usize) == self.program.len()
+        self.halted || (self.ip as usize) == self.program.len()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum StopReason {
+    Stepped,
+    Terminated,
+    Fault(Fault),
+    Breakpoint(i32),
+    AccChanged { old: i32, new: i32 },
+    // The instruction pointers making up the repeated cycle, starting
+    // with the instruction that is about to be repeated.
+    Loop(Vec<i32>),
+}
+
+// Log of every executed instruction, along with the acc at the time
+// it was executed.
+#[derive(Debug)]
+struct Trace {
+    entries: Vec<(i32, i32)>,
+    hits: Vec<usize>,
+}
+
+impl Trace {
+    fn new(program_len: usize) -> Self {
+        Self {
+            entries: Vec::new(),
+            hits: vec![0; program_len],
+        }
+    }
+
+    fn record(&mut self, ip: i32, acc: i32) {
+        self.entries.push((ip, acc));
+        self.hits[ip as usize] += 1;
+    }
+
+    // Writes one "ip acc instruction" line per executed instruction.
+    fn write<W: Write>(
+        &self,
+        program: &[Instruction],
+        writer: &mut W,
+    ) -> std::io::Result<()> {
+        self.entries.iter().try_for_each(|&(ip, acc)| {
+            writeln!(writer, "{} {} {}", ip, acc, program[ip as usize])
+        })
+    }
+
+    // Lists the program with the number of times each instruction
+    // was executed, and a bar scaled to the most-executed one.
+    fn heat_map(&self, program: &[Instruction]) -> String {
+        const BAR_WIDTH: usize = 40;
+        let max_hits = self.hits.iter().copied().max().unwrap_or(0).max(1);
+
+        program
+            .iter()
+            .zip(self.hits.iter())
+            .enumerate()
+            .map(|(ip, (instruction, &hits))| {
+                let bar_len = (hits * BAR_WIDTH).div_ceil(max_hits);
+                format!(
+                    "{:8} {:width$} {:4}: {}\n",
+                    hits,
+                    "#".repeat(bar_len),
+                    ip,
+                    instruction,
+                    width = BAR_WIDTH
+                )
+            })
+            .collect()
     }
 }
 
+// Everything needed to undo a single step of the virtual machine.
+#[derive(Debug, Clone, Copy)]
+struct Snapshot {
+    ip: i32,
+    acc: i32,
+    registers: [i32; NUM_REGISTERS],
+    output_len: usize,
+    halted: bool,
+}
+
+type LoopKey = (i32, i32, [i32; NUM_REGISTERS]);
+
 #[derive(Debug)]
-struct InfiniteLoop;
+struct Debugger {
+    vm: VirtualMachine,
+    // State before each executed instruction, for reverse stepping.
+    history: Vec<Snapshot>,
+    // Index into history at which each state was first executed.
+    first_visit: HashMap<LoopKey, usize>,
+    // Without conditional jumps, control flow cannot depend on the
+    // registers, and revisiting an ip is enough to know it loops.
+    data_dependent: bool,
+    breakpoints: HashSet<i32>,
+    watch_acc: bool,
+    trace: Option<Trace>,
+}
+
+impl From<VirtualMachine> for Debugger {
+    fn from(vm: VirtualMachine) -> Self {
+        let data_dependent = vm.program.iter().any(|instruction| {
+            matches!(instruction, Instruction::Jz(..) | Instruction::Jnz(..))
+        });
+        Self {
+            vm,
+            history: Vec::new(),
+            first_visit: HashMap::new(),
+            data_dependent,
+            breakpoints: HashSet::new(),
+            watch_acc: false,
+            trace: None,
+        }
+    }
+}
+
+impl Debugger {
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            ip: self.vm.ip,
+            acc: self.vm.acc,
+            registers: self.vm.registers,
+            output_len: self.vm.output.len(),
+            halted: self.vm.halted,
+        }
+    }
+
+    fn restore(&mut self, snapshot: &Snapshot) {
+        self.vm.ip = snapshot.ip;
+        self.vm.acc = snapshot.acc;
+        self.vm.registers = snapshot.registers;
+        self.vm.output.truncate(snapshot.output_len);
+        self.vm.halted = snapshot.halted;
+    }
+
+    fn loop_key(&self) -> LoopKey {
+        if self.data_dependent {
+            (self.vm.ip, self.vm.acc, self.vm.registers)
+        } else {
+            (self.vm.ip, 0, [0; NUM_REGISTERS])
+        }
+    }
+
+    fn step(&mut self) -> StopReason {
+        if self.vm.terminated() {
+            return StopReason::Terminated;
+        }
+
+        let snapshot = self.snapshot();
+        let key = self.loop_key();
+        if let Err(fault) = self.vm.iter() {
+            self.restore(&snapshot);
+            return StopReason::Fault(fault);
+        }
+        self.first_visit.entry(key).or_insert(self.history.len());
+        self.history.push(snapshot);
+        if let Some(trace) = &mut self.trace {
+            trace.record(snapshot.ip, snapshot.acc);
+        }
+
+        if self.vm.terminated() {
+            StopReason::Terminated
+        } else if let Some(&start) = self.first_visit.get(&self.loop_key()) {
+            StopReason::Loop(
+                self.history[start..]
+                    .iter()
+                    .map(|snapshot| snapshot.ip)
+                    .collect(),
+            )
+        } else if self.breakpoints.contains(&self.vm.ip) {
+            StopReason::Breakpoint(self.vm.ip)
+        } else if self.watch_acc && snapshot.acc != self.vm.acc {
+            StopReason::AccChanged {
+                old: snapshot.acc,
+                new: self.vm.acc,
+            }
+        } else {
+            StopReason::Stepped
+        }
+    }
+
+    // Undoes the most recent step, returning false if there is no
+    // history to undo.
+    fn step_back(&mut self) -> bool {
+        match self.history.pop() {
+            None => false,
+            Some(snapshot) => {
+                self.restore(&snapshot);
+                let key = self.loop_key();
+                if self.first_visit.get(&key) == Some(&self.history.len()) {
+                    self.first_visit.remove(&key);
+                }
+                true
+            }
+        }
+    }
+
+    fn run(&mut self) -> StopReason {
+        loop {
+            let reason = self.step();
+            if reason != StopReason::Stepped {
+                return reason;
+            }
+        }
+    }
+
+    fn window(&self, radius: usize) -> String {
+        let ip = self.vm.ip.max(0) as usize;
+        let start = ip.saturating_sub(radius);
+        let end = ip
+            .saturating_add(radius)
+            .saturating_add(1)
+            .min(self.vm.program.len());
+
+        (start..end)
+            .map(|i| {
+                format!(
+                    "{}{} {:4}: {}\n",
+                    if i == ip { "=>" } else { "  " },
+                    if self.breakpoints.contains(&(i as i32)) {
+                        "*"
+                    } else {
+                        " "
+                    },
+                    i,
+                    self.vm.program[i]
+                )
+            })
+            .collect()
+    }
+
+    fn describe(&self, reason: &StopReason) -> String {
+        match reason {
+            StopReason::Stepped => {
+                format!("ip = {}, acc = {}", self.vm.ip, self.vm.acc)
+            }
+            StopReason::Terminated => {
+                format!("Program terminated with acc = {}", self.vm.acc)
+            }
+            StopReason::Fault(fault) => {
+                format!("Fault: {}", fault)
+            }
+            StopReason::Breakpoint(ip) => {
+                format!("Breakpoint at {}, acc = {}", ip, self.vm.acc)
+            }
+            StopReason::AccChanged { old, new } => format!(
+                "acc changed from {} to {} at ip = {}",
+                old, new, self.vm.ip
+            ),
+            StopReason::Loop(cycle) => format!(
+                "Loop of {} instructions detected at ip = {}, acc = {}: {}",
+                cycle.len(),
+                self.vm.ip,
+                self.vm.acc,
+                cycle
+                    .iter()
+                    .map(|ip| format!(
+                        "{} ({})",
+                        ip, self.vm.program[*ip as usize]
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+
+    // Runs a single debugger command, returning false when the user
+    // has asked to quit.
+    fn command(&mut self, line: &str) -> Result<bool, util::Error> {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let arg = |default: i32| -> Result<i32, util::Error> {
+            Ok(match words.get(1) {
+                Some(word) => word.parse::<i32>()?,
+                None => default,
+            })
+        };
+
+        match words.first().copied() {
+            None => {}
+            Some("s") | Some("step") => {
+                let mut reason = StopReason::Stepped;
+                for _ in 0..arg(1)? {
+                    reason = self.step();
+                    if reason != StopReason::Stepped {
+                        break;
+                    }
+                }
+                println!("{}", self.describe(&reason));
+            }
+            Some("r") | Some("back") => {
+                for _ in 0..arg(1)? {
+                    if !self.step_back() {
+                        println!("No further history");
+                        break;
+                    }
+                }
+                println!("{}", self.describe(&StopReason::Stepped));
+            }
+            Some("c") | Some("continue") => {
+                let reason = self.run();
+                println!("{}", self.describe(&reason));
+            }
+            Some("b") | Some("break") => {
+                self.breakpoints.insert(arg(self.vm.ip)?);
+            }
+            Some("d") | Some("delete") => {
+                self.breakpoints.remove(&arg(self.vm.ip)?);
+            }
+            Some("w") | Some("watch") => {
+                self.watch_acc = !self.watch_acc;
+                println!(
+                    "Watch on acc {}",
+                    if self.watch_acc {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    }
+                );
+            }
+            Some("l") | Some("list") => {
+                let radius = arg(3)?;
+                if radius < 0 {
+                    return Err(util::Error::InvalidValue(radius.to_string()));
+                }
+                print!("{}", self.window(radius as usize));
+            }
+            Some("p") | Some("print") => {
+                println!("{}", self.describe(&StopReason::Stepped));
+                self.vm
+                    .registers
+                    .iter()
+                    .enumerate()
+                    .filter(|(_i, &val)| val != 0)
+                    .for_each(|(i, val)| {
+                        println!("{} = {}", Register::Named(i), val)
+                    });
+                if !self.vm.output.is_empty() {
+                    println!("Output: {:?}", self.vm.output);
+                }
+            }
+            Some("q") | Some("quit") => return Ok(false),
+            Some(command) => {
+                return Err(util::Error::InvalidValue(command.to_owned()))
+            }
+        }
+
+        Ok(true)
+    }
 
-fn test_swap(
-    mut program: Vec<Instruction>,
+    fn interactive(&mut self) -> Result<(), util::Error> {
+        let stdin = std::io::stdin();
+        loop {
+            print!("(ip {}) ", self.vm.ip);
+            std::io::stdout().flush()?;
+
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+
+            match self.command(&line) {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(err) => println!("Error: {:?}", err),
+            }
+        }
+    }
+}
+
+// A single jmp/nop flip that makes the program terminate.
+#[derive(Debug)]
+struct Repair {
     loc: usize,
-) -> Result<i32, InfiniteLoop> {
-    match program[loc] {
-        Instruction::Acc(_) => {}
-        Instruction::Jmp(val) => program[loc] = Instruction::Nop(val),
-        Instruction::Nop(val) => program[loc] = Instruction::Jmp(val),
+    replacement: Instruction,
+    acc: i32,
+}
+
+// Static analysis of the program's control flow, where node
+// program.len() represents successful termination.
+#[derive(Debug)]
+struct ControlFlow {
+    successors: Vec<Option<usize>>,
+    // Change in acc while executing each instruction.
+    acc_delta: Vec<i32>,
+    // For nodes that terminate, the total change in acc between
+    // reaching that node and termination.
+    acc_to_end: Vec<Option<i32>>,
+}
+
+impl ControlFlow {
+    fn new(program: &[Instruction]) -> Result<Self, util::Error> {
+        // Only instructions with a fixed successor and a fixed change
+        // to acc can be analyzed statically.
+        if let Some(instruction) = program.iter().find(|instruction| {
+            !matches!(
+                instruction,
+                Instruction::Acc(_)
+                    | Instruction::Jmp(_)
+                    | Instruction::Nop(_)
+                    | Instruction::Halt
+            )
+        }) {
+            return Err(util::Error::InvalidValue(instruction.to_string()));
+        }
+
+        let end = program.len();
+        let successors = program
+            .iter()
+            .enumerate()
+            .map(|(ip, instruction)| ControlFlow::next_ip(instruction, ip, end))
+            .collect::<Vec<_>>();
+        let acc_delta = program
+            .iter()
+            .map(|instruction| match instruction {
+                Instruction::Acc(val) => *val,
+                _ => 0,
+            })
+            .collect::<Vec<_>>();
+
+        let mut predecessors = vec![Vec::new(); end + 1];
+        successors.iter().enumerate().for_each(|(ip, next)| {
+            if let Some(next) = next {
+                predecessors[*next].push(ip);
+            }
+        });
+
+        // Every node has at most one successor, so the nodes that
+        // terminate form a tree rooted at the end node.
+        let mut acc_to_end = vec![None; end + 1];
+        acc_to_end[end] = Some(0);
+        let mut unchecked = vec![end];
+        while let Some(node) = unchecked.pop() {
+            for &prev in &predecessors[node] {
+                acc_to_end[prev] =
+                    acc_to_end[node].map(|acc| acc + acc_delta[prev]);
+                unchecked.push(prev);
+            }
+        }
+
+        Ok(Self {
+            successors,
+            acc_delta,
+            acc_to_end,
+        })
     }
 
-    let mut vm = VirtualMachine::from(program);
-    let mut visited_instructions = HashSet::<i32>::new();
-    loop {
-        if visited_instructions.contains(&vm.ip) || vm.terminated() {
-            break;
+    // The instruction executed after the given one, or None if it
+    // would jump outside of the program.
+    fn next_ip(
+        instruction: &Instruction,
+        ip: usize,
+        end: usize,
+    ) -> Option<usize> {
+        let offset = match instruction {
+            Instruction::Jmp(val) => *val,
+            Instruction::Halt => return Some(end),
+            _ => 1,
+        };
+        let next = ip as i64 + offset as i64;
+        if next >= 0 && next as usize <= end {
+            Some(next as usize)
+        } else {
+            None
         }
-        visited_instructions.insert(vm.ip);
-        vm.iter();
     }
 
-    if vm.terminated() {
-        Ok(vm.acc)
-    } else {
-        Err(InfiniteLoop)
+    fn find_repairs(&self, program: &[Instruction]) -> Vec<Repair> {
+        let end = program.len();
+        let mut output = Vec::new();
+
+        // acc_to_end describes the unflipped program, so it is only valid
+        // for a flip if the path from the new target never returns to the
+        // flipped instruction.  That holds when the original program
+        // loops, as nothing on its path reaches the end.  A program that
+        // already terminates needs no repair.
+        if self.acc_to_end[0].is_some() {
+            return output;
+        }
+
+        let mut visited = vec![false; end];
+        let mut acc = 0;
+        let mut ip = 0;
+
+        // Only instructions executed by the original program can
+        // change its behavior when flipped.
+        while ip < end && !visited[ip] {
+            visited[ip] = true;
+
+            let flipped = match program[ip] {
+                Instruction::Jmp(val) => Some(Instruction::Nop(val)),
+                Instruction::Nop(val) => Some(Instruction::Jmp(val)),
+                _ => None,
+            };
+            if let Some(replacement) = flipped {
+                if let Some(acc_after) =
+                    ControlFlow::next_ip(&replacement, ip, end)
+                        .and_then(|next| self.acc_to_end[next])
+                {
+                    output.push(Repair {
+                        loc: ip,
+                        replacement,
+                        acc: acc + acc_after,
+                    });
+                }
+            }
+
+            acc += self.acc_delta[ip];
+            match self.successors[ip] {
+                Some(next) => ip = next,
+                None => break,
+            }
+        }
+
+        output
     }
 }
 
@@ -97,28 +919,64 @@ fn main() -> Result<(), util::Error> {
     let args: Vec<String> = std::env::args().collect();
     let filename = &args[1];
 
-    let program = util::file_lines(filename)
-        .unwrap()
-        .map(|line| line?.parse::<Instruction>())
-        .collect::<Result<Vec<_>, _>>()?;
+    let lines = util::file_lines(filename)?.collect::<Result<Vec<_>, _>>()?;
+    let program = assemble(&lines)?;
 
-    let mut vm = VirtualMachine::from(program.clone());
+    if util::has_flag(&args, "disassemble") {
+        print!("{}", disassemble(&program));
+        return Ok(());
+    }
 
-    let mut visited_instructions = HashSet::<i32>::new();
-    while !visited_instructions.contains(&vm.ip) {
-        visited_instructions.insert(vm.ip);
-        vm.iter();
+    let mut debugger = Debugger::from(VirtualMachine::from(program.clone()));
+    let trace_file = util::arg_value(&args, "trace");
+    let profile = util::has_flag(&args, "profile");
+    if trace_file.is_some() || profile {
+        debugger.trace = Some(Trace::new(program.len()));
+    }
+    if util::has_flag(&args, "debug") {
+        return debugger.interactive();
     }
-    println!("First repeated instruction {} with acc = {}", vm.ip, vm.acc);
 
-    let terminal_values = program
-        .iter()
-        .enumerate()
-        .map(|(i, _instruction)| test_swap(program.clone(), i))
-        .filter(|res| res.is_ok())
-        .map(|res| res.unwrap())
-        .collect::<Vec<_>>();
-    println!("Terminal values = {:?}", terminal_values);
+    match debugger.run() {
+        StopReason::Loop(_) => println!(
+            "First repeated instruction {} with acc = {}",
+            debugger.vm.ip, debugger.vm.acc
+        ),
+        reason => println!("{}", debugger.describe(&reason)),
+    }
+    if !debugger.vm.output.is_empty() {
+        println!("Output: {:?}", debugger.vm.output);
+    }
+
+    if let Some(trace) = &debugger.trace {
+        if let Some(trace_file) = trace_file {
+            let mut writer =
+                std::io::BufWriter::new(std::fs::File::create(trace_file)?);
+            trace.write(&program, &mut writer)?;
+        }
+        if profile {
+            print!("{}", trace.heat_map(&program));
+        }
+    }
+
+    match ControlFlow::new(&program) {
+        Ok(control_flow) => {
+            let repairs = control_flow.find_repairs(&program);
+            repairs.iter().for_each(|repair| {
+                println!(
+                    "Replacing {} at {} with {} gives acc = {}",
+                    program[repair.loc],
+                    repair.loc,
+                    repair.replacement,
+                    repair.acc
+                );
+            });
+            let terminal_values =
+                repairs.iter().map(|repair| repair.acc).collect::<Vec<_>>();
+            println!("Terminal values = {:?}", terminal_values);
+        }
+        Err(err) => println!("Cannot search for repairs: {:?}", err),
+    }
 
     Ok(())
 }
@@ -1,58 +1,149 @@
 use itertools_num::ItertoolsNum;
-use std::collections::HashSet;
+use std::collections::{HashMap, VecDeque};
 
-fn last_is_sum_of_previous(seq: &[i64]) -> bool {
-    let (last, elements) = seq.split_last().unwrap();
-    let elements: HashSet<_> = elements.iter().collect();
+// Checks each number against the sums of pairs of the previous
+// preamble_len numbers, updating the pair sums as the window slides
+// rather than rebuilding them for every number.
+#[derive(Debug)]
+struct XmasValidator {
+    preamble_len: usize,
+    window: VecDeque<i64>,
+    pair_sums: HashMap<i64, usize>,
+}
+
+impl XmasValidator {
+    fn new(preamble_len: usize) -> Self {
+        Self {
+            preamble_len,
+            window: VecDeque::with_capacity(preamble_len + 1),
+            pair_sums: HashMap::new(),
+        }
+    }
+
+    // Adds the next number, returning whether it was valid, or None
+    // while the preamble is still being read.
+    fn push(&mut self, value: i64) -> Option<bool> {
+        let is_valid = if self.window.len() == self.preamble_len {
+            Some(self.pair_sums.get(&value).copied().unwrap_or(0) > 0)
+        } else {
+            None
+        };
+
+        if self.window.len() == self.preamble_len {
+            if let Some(oldest) = self.window.pop_front() {
+                for &other in self.window.iter().filter(|&&x| x != oldest) {
+                    let sum = oldest + other;
+                    let count = self.pair_sums.get_mut(&sum).unwrap();
+                    *count -= 1;
+                    if *count == 0 {
+                        self.pair_sums.remove(&sum);
+                    }
+                }
+            }
+        }
+
+        for &other in self.window.iter().filter(|&&x| x != value) {
+            *self.pair_sums.entry(value + other).or_insert(0) += 1;
+        }
+        if self.preamble_len > 0 {
+            self.window.push_back(value);
+        }
 
-    elements.iter().any(|val| elements.contains(&(last - *val)))
+        is_valid
+    }
 }
 
-fn contiguous_sum_indices(
+// Finds every inclusive range of at least min_len numbers summing to
+// target_val.  Matching prefix sums are looked up by value, so any
+// mix of positive, negative and zero values is allowed.
+fn contiguous_sum_ranges(
     seq: &[i64],
     target_val: i64,
-) -> Option<(usize, usize)> {
-    let cumsum: Vec<i64> = seq.iter().cumsum().collect();
-
-    cumsum
-        .iter()
-        .enumerate()
-        .map(|(i, first)| {
-            let target_last = first + target_val;
-            let res = cumsum.binary_search(&target_last);
-            match res {
-                Ok(last_i) => Some((i, last_i)),
-                Err(_) => None,
-            }
-        })
-        .find(|res| res.is_some())
-        .unwrap()
+    min_len: usize,
+) -> Vec<(usize, usize)> {
+    // prefix_sums[i] is the sum of seq[..i]
+    let prefix_sums = std::iter::once(0)
+        .chain(seq.iter().cumsum())
+        .collect::<Vec<i64>>();
+
+    let mut seen = HashMap::<i64, Vec<usize>>::new();
+    let mut output = Vec::new();
+    for end in 0..prefix_sums.len() {
+        if end >= min_len.max(1) {
+            let start_prefix = end - min_len.max(1);
+            seen.entry(prefix_sums[start_prefix])
+                .or_default()
+                .push(start_prefix);
+        }
+        if let Some(starts) = seen.get(&(prefix_sums[end] - target_val)) {
+            output.extend(starts.iter().map(|&start| (start, end - 1)));
+        }
+    }
+
+    output
 }
 
-fn first_out_of_sequence(sequence: &[i64], preamble_len: usize) -> Option<i64> {
-    sequence
-        .windows(preamble_len + 1)
-        .find(|seq| !last_is_sum_of_previous(seq))
-        .map(|seq| *seq.last().unwrap())
+fn contiguous_sum_indices(
+    seq: &[i64],
+    target_val: i64,
+    min_len: usize,
+) -> Option<(usize, usize)> {
+    contiguous_sum_ranges(seq, target_val, min_len)
+        .into_iter()
+        .next()
 }
 
 fn main() -> Result<(), util::Error> {
     let args: Vec<String> = std::env::args().collect();
     let filename = &args[1];
-    let preamble_len = args[2].parse::<usize>()?;
+    let preamble_len = util::arg_value(&args, "preamble")
+        .map(|val| val.parse::<usize>())
+        .transpose()?
+        .unwrap_or(25);
+
+    let mut validator = XmasValidator::new(preamble_len);
+    let mut invalid_numbers = Vec::new();
+    for (i, line) in util::file_lines(filename)?.enumerate() {
+        let value = line?.parse::<i64>()?;
+        if validator.push(value) == Some(false) {
+            invalid_numbers.push((i, value));
+        }
+    }
+    invalid_numbers.iter().for_each(|(i, value)| {
+        println!("Line {} is out of sequence: {}", i + 1, value);
+    });
 
-    let sequence = util::file_lines(filename)
-        .unwrap()
+    let invalid_number = invalid_numbers
+        .first()
+        .map(|(_i, value)| *value)
+        .ok_or(util::Error::NoneError)?;
+    println!("Part a, first out of sequence: {:?}", invalid_number);
+
+    let sequence = util::file_lines(filename)?
         .map(|line| -> Result<_, util::Error> { Ok(line?.parse::<i64>()?) })
         .collect::<Result<Vec<_>, _>>()?;
 
-    let invalid_number =
-        first_out_of_sequence(&sequence[..], preamble_len).unwrap();
-    println!("Part a, first out of sequence: {:?}", invalid_number);
+    let min_len = util::arg_value(&args, "min-len")
+        .map(|val| val.parse::<usize>())
+        .transpose()?
+        .unwrap_or(2);
+
+    let ranges = contiguous_sum_ranges(&sequence[..], invalid_number, min_len);
+    println!(
+        "Part b, {} contiguous ranges sum to {}",
+        ranges.len(),
+        invalid_number
+    );
 
     let contiguous_range =
-        contiguous_sum_indices(&sequence[..], invalid_number).unwrap();
-    let (ia, ib) = contiguous_range;
+        contiguous_sum_indices(&sequence[..], invalid_number, min_len);
+    let (ia, ib) = match contiguous_range {
+        Some(range) => range,
+        None => {
+            println!("Part b, no contiguous range found");
+            return Ok(());
+        }
+    };
     println!(
         "Part b, continguous range {} - {}",
         sequence[ia], sequence[ib]
@@ -1,5 +1,8 @@
 use std::collections::HashMap;
 
+use num_bigint::BigUint;
+use num_traits::{One, Zero};
+
 // fn count_instances<'a, I, T>(iter: I) -> HashMap<T,i32>
 //     where I: Iterator<Item = &'a T>
 // {
@@ -8,23 +11,202 @@ use std::collections::HashMap;
 //     output
 // }
 
-fn find_num_paths(joltages: &[i32]) -> HashMap<i32, i64> {
+// Number of ways to reach each joltage from the wall, given the
+// sorted joltages and the allowed differences between adapters.
+fn find_num_paths(joltages: &[i32], gaps: &[i32]) -> HashMap<i32, BigUint> {
     let mut paths = HashMap::new();
-    paths.insert(0, 1);
-
-    joltages.iter().for_each(|val| {
-        if *val > 0 {
-            let num_paths_to = |val| *paths.get(&val).or(Some(&0)).unwrap();
-            let num_new_paths = num_paths_to(val - 3)
-                + num_paths_to(val - 2)
-                + num_paths_to(val - 1);
-            paths.insert(*val, num_new_paths);
-        }
+    paths.insert(joltages[0], BigUint::one());
+
+    joltages.iter().skip(1).for_each(|val| {
+        let num_new_paths =
+            gaps.iter().filter_map(|gap| paths.get(&(val - gap))).sum();
+        paths.insert(*val, num_new_paths);
     });
 
     paths
 }
 
+#[derive(Debug)]
+struct AdapterChain {
+    // Sorted, starting with the wall and ending with the device.
+    joltages: Vec<i32>,
+    gaps: Vec<i32>,
+    paths_to: HashMap<i32, BigUint>,
+    paths_from: HashMap<i32, BigUint>,
+}
+
+impl AdapterChain {
+    fn new(joltages: Vec<i32>, gaps: Vec<i32>) -> Self {
+        let paths_to = find_num_paths(&joltages, &gaps);
+
+        // Counting paths to the wall over negated joltages gives the
+        // number of paths from each adapter to the device.
+        let negated = joltages.iter().rev().map(|val| -val).collect::<Vec<_>>();
+        let paths_from = find_num_paths(&negated, &gaps)
+            .into_iter()
+            .map(|(val, num)| (-val, num))
+            .collect();
+
+        Self {
+            joltages,
+            gaps,
+            paths_to,
+            paths_from,
+        }
+    }
+
+    fn wall(&self) -> i32 {
+        self.joltages[0]
+    }
+
+    fn device(&self) -> i32 {
+        *self.joltages.last().unwrap()
+    }
+
+    fn num_arrangements(&self) -> BigUint {
+        self.paths_to[&self.device()].clone()
+    }
+
+    // Number of arrangements that use the adapter.
+    fn num_using(&self, joltage: i32) -> BigUint {
+        &self.paths_to[&joltage] * &self.paths_from[&joltage]
+    }
+
+    // Adapters that appear in every arrangement.
+    fn mandatory(&self) -> Vec<i32> {
+        let total = self.num_arrangements();
+        self.joltages[1..self.joltages.len() - 1]
+            .iter()
+            .copied()
+            .filter(|&val| !total.is_zero() && self.num_using(val) == total)
+            .collect()
+    }
+
+    // Adapters that appear in some, but not all, arrangements.
+    fn optional(&self) -> Vec<i32> {
+        let total = self.num_arrangements();
+        self.joltages[1..self.joltages.len() - 1]
+            .iter()
+            .copied()
+            .filter(|&val| {
+                let num = self.num_using(val);
+                !num.is_zero() && num != total
+            })
+            .collect()
+    }
+
+    // Adapters reachable in a single step that can still reach the
+    // device, in increasing order.
+    fn next_adapters(&self, joltage: i32) -> impl Iterator<Item = i32> + '_ {
+        let mut next = self
+            .gaps
+            .iter()
+            .map(move |gap| joltage + gap)
+            .filter(move |val| {
+                self.paths_from.get(val).is_some_and(|num| !num.is_zero())
+            })
+            .collect::<Vec<_>>();
+        next.sort_unstable();
+        next.dedup();
+        next.into_iter()
+    }
+
+    fn arrangements(&self) -> Arrangements<'_> {
+        let can_reach = self
+            .paths_from
+            .get(&self.wall())
+            .is_some_and(|num| !num.is_zero());
+        Arrangements {
+            chain: self,
+            stack: if can_reach {
+                vec![(self.wall(), self.next_adapters(self.wall()).collect())]
+            } else {
+                Vec::new()
+            },
+        }
+    }
+
+    // Returns the arrangement at the given position in the order
+    // produced by arrangements(), without generating the ones before.
+    fn nth_arrangement(&self, index: &BigUint) -> Option<Vec<i32>> {
+        if index >= &self.num_arrangements() {
+            return None;
+        }
+
+        let mut index = index.clone();
+        let mut output = vec![self.wall()];
+        let mut joltage = self.wall();
+        while joltage != self.device() {
+            for next in self.next_adapters(joltage) {
+                let num = &self.paths_from[&next];
+                if &index < num {
+                    joltage = next;
+                    break;
+                }
+                index -= num;
+            }
+            output.push(joltage);
+        }
+
+        Some(output)
+    }
+}
+
+// Lazily walks every arrangement of adapters, depth-first.
+struct Arrangements<'a> {
+    chain: &'a AdapterChain,
+    // Each joltage in the current chain, along with the adapters
+    // that have yet to be tried after it.
+    stack: Vec<(i32, Vec<i32>)>,
+}
+
+impl<'a> Iterator for Arrangements<'a> {
+    type Item = Vec<i32>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (joltage, remaining) = self.stack.last_mut()?;
+            if *joltage == self.chain.device() {
+                let output =
+                    self.stack.iter().map(|(val, _)| *val).collect::<Vec<_>>();
+                self.stack.pop();
+                return Some(output);
+            }
+
+            if remaining.is_empty() {
+                self.stack.pop();
+            } else {
+                let next = remaining.remove(0);
+                let after = self.chain.next_adapters(next).collect();
+                self.stack.push((next, after));
+            }
+        }
+    }
+}
+
+// Small xorshift generator, for sampling arrangements reproducibly.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, limit: &BigUint) -> BigUint {
+        let num_words = (limit.bits() / 64 + 2) as usize;
+        let digits = (0..num_words).map(|_| self.next()).collect::<Vec<_>>();
+        BigUint::from_slice(
+            &digits
+                .iter()
+                .flat_map(|val| vec![*val as u32, (val >> 32) as u32])
+                .collect::<Vec<_>>(),
+        ) % limit
+    }
+}
+
 fn main() -> Result<(), util::Error> {
     let args: Vec<String> = std::env::args().collect();
     let filename = &args[1];
@@ -34,8 +216,34 @@ fn main() -> Result<(), util::Error> {
         .map(|line| -> Result<_, util::Error> { Ok(line?.parse::<i32>()?) })
         .collect::<Result<Vec<_>, _>>()?;
 
+    let mut gaps = match util::arg_value(&args, "gaps") {
+        Some(gaps) => gaps
+            .split(',')
+            .map(|gap| gap.parse::<i32>())
+            .collect::<Result<Vec<_>, _>>()?,
+        None => vec![1, 2, 3],
+    };
+    gaps.sort_unstable();
+    gaps.dedup();
+    if gaps.first().is_none_or(|&gap| gap <= 0) {
+        return Err(util::Error::InvalidValue(format!("{:?}", gaps)));
+    }
+
+    // The device is rated this far above the highest adapter, independent of
+    // the gaps allowed between adapters (the puzzle fixes it at 3).
+    let device_offset = util::arg_value(&args, "device-offset")
+        .map(|val| val.parse::<i32>())
+        .transpose()?
+        .unwrap_or(3);
+    if device_offset <= 0 {
+        return Err(util::Error::InvalidValue(format!(
+            "--device-offset {}",
+            device_offset
+        )));
+    }
+
     joltages.push(0); // Wall joltage
-    let device_joltage = joltages.iter().max().unwrap() + 3;
+    let device_joltage = joltages.iter().max().unwrap() + device_offset;
     joltages.push(device_joltage); // Device adapter
 
     joltages.sort_unstable();
@@ -48,15 +256,44 @@ fn main() -> Result<(), util::Error> {
             *difference_counts.entry(diff).or_insert(0) += 1;
         });
 
+    let num_diffs = |diff| *difference_counts.get(&diff).unwrap_or(&0);
     println!(
         "1J diffs = {}, 3J diffs = {}, prod = {}",
-        difference_counts[&1],
-        difference_counts[&3],
-        difference_counts[&1] * difference_counts[&3]
+        num_diffs(1),
+        num_diffs(3),
+        num_diffs(1) * num_diffs(3)
     );
 
-    let num_paths = find_num_paths(&joltages);
-    println!("Num paths = {}", num_paths[&device_joltage]);
+    let chain = AdapterChain::new(joltages, gaps);
+    println!("Num paths = {}", chain.num_arrangements());
+    println!("Mandatory adapters: {:?}", chain.mandatory());
+    println!("Optional adapters: {:?}", chain.optional());
+
+    if let Some(num) = util::arg_value(&args, "list") {
+        chain
+            .arrangements()
+            .take(num.parse::<usize>()?)
+            .for_each(|arrangement| println!("{:?}", arrangement));
+    }
+
+    if let Some(num) = util::arg_value(&args, "sample") {
+        let seed = util::arg_value(&args, "seed")
+            .map(|val| val.parse::<u64>())
+            .transpose()?
+            .unwrap_or(0x2020);
+        let mut rng = XorShift(seed.max(1));
+        let total = chain.num_arrangements();
+        if !total.is_zero() {
+            for _ in 0..num.parse::<usize>()? {
+                let index = rng.below(&total);
+                println!(
+                    "#{}: {:?}",
+                    index,
+                    chain.nth_arrangement(&index).unwrap()
+                );
+            }
+        }
+    }
 
     Ok(())
 }
@@ -1,11 +1,156 @@
-#[derive(Debug, Copy, Clone, PartialEq)]
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+#[derive(Debug, Copy, Clone, PartialEq, Hash)]
 enum CellState {
     OccupiedChair,
     EmptyChair,
     Floor,
 }
 
+const DIRECTIONS: [(i32, i32); 8] = [
+    (1, 1),
+    (1, 0),
+    (1, -1),
+    (0, 1),
+    (0, -1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Neighborhood {
+    // The eight surrounding cells.
+    Adjacent,
+    // The first chair seen in each of the eight directions.
+    LineOfSight,
+    // The cells at each of the given offsets.
+    Offsets(Vec<(i32, i32)>),
+}
+
+impl std::str::FromStr for Neighborhood {
+    type Err = util::Error;
+
+    // Accepts "adjacent", "line-of-sight", or a list of offsets such
+    // as "offsets:1,0;-1,0;0,2".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "adjacent" => Ok(Neighborhood::Adjacent),
+            "line-of-sight" => Ok(Neighborhood::LineOfSight),
+            _ => {
+                let offsets = s
+                    .strip_prefix("offsets:")
+                    .ok_or_else(|| util::Error::InvalidValue(s.to_owned()))?;
+                Ok(Neighborhood::Offsets(
+                    offsets
+                        .split(';')
+                        .map(|offset| {
+                            let (dx, dy) =
+                                offset.split_once(',').ok_or_else(|| {
+                                    util::Error::InvalidValue(offset.to_owned())
+                                })?;
+                            Ok((dx.trim().parse()?, dy.trim().parse()?))
+                        })
+                        .collect::<Result<Vec<_>, util::Error>>()?,
+                ))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Rules {
+    neighborhood: Neighborhood,
+    // An empty chair is occupied if at most this many neighbors are.
+    occupy_threshold: usize,
+    // An occupied chair is vacated if at least this many neighbors are.
+    vacate_threshold: usize,
+    // Whether neighbors past one edge are found at the opposite edge.
+    wrap: bool,
+}
+
+impl Rules {
+    fn part1() -> Self {
+        Self {
+            neighborhood: Neighborhood::Adjacent,
+            occupy_threshold: 0,
+            vacate_threshold: 4,
+            wrap: false,
+        }
+    }
+
+    fn part2() -> Self {
+        Self {
+            neighborhood: Neighborhood::LineOfSight,
+            occupy_threshold: 0,
+            vacate_threshold: 5,
+            wrap: false,
+        }
+    }
+
+    // Overrides any rules given on the command line, returning None
+    // if no rules were given.
+    fn from_args(
+        args: &[String],
+        base: Self,
+    ) -> Result<Option<Self>, util::Error> {
+        let neighborhood = util::arg_value(args, "neighborhood");
+        let occupy = util::arg_value(args, "occupy");
+        let vacate = util::arg_value(args, "vacate");
+        let wrap = util::has_flag(args, "wrap");
+        if neighborhood.is_none()
+            && occupy.is_none()
+            && vacate.is_none()
+            && !wrap
+        {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            neighborhood: neighborhood
+                .map(|s| s.parse())
+                .transpose()?
+                .unwrap_or(base.neighborhood),
+            occupy_threshold: occupy
+                .map(|s| s.parse())
+                .transpose()?
+                .unwrap_or(base.occupy_threshold),
+            vacate_threshold: vacate
+                .map(|s| s.parse())
+                .transpose()?
+                .unwrap_or(base.vacate_threshold),
+            wrap: wrap || base.wrap,
+        }))
+    }
+}
+
+// The seats that each seat counts as neighbors, and the reverse.
+#[derive(Debug)]
+struct SeatGraph {
+    neighbors: Vec<Vec<usize>>,
+    dependents: Vec<Vec<usize>>,
+}
+
+// How a simulation ended.  Generation 0 is the initial state.
 #[derive(Debug, PartialEq)]
+enum Outcome {
+    // The state no longer changes from this generation onward.
+    Stable { generation: usize },
+    // The state at generation start + period repeats that at start.
+    Periodic { start: usize, period: usize },
+}
+
+#[derive(Debug)]
+struct Simulation {
+    outcome: Outcome,
+    final_state: Ferry,
+    // Every generation from the initial state onward, if requested.
+    history: Vec<Ferry>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 struct Ferry {
     states: Vec<CellState>,
     height: usize,
@@ -57,109 +202,130 @@ impl Ferry {
         (x, y)
     }
 
-    fn get_value(&self, x: i32, y: i32) -> CellState {
-        if self.in_bounds(x, y) {
-            let x = x as usize;
-            let y = y as usize;
-            let loc = (y * self.width + x) as usize;
-            self.states[loc]
+    fn as_loc(&self, x: i32, y: i32) -> usize {
+        (y as usize) * self.width + (x as usize)
+    }
+
+    // Moves from (x,y) by (dx,dy), returning None if the new location
+    // is outside the ferry and the rules don't wrap around.
+    fn offset(
+        &self,
+        x: i32,
+        y: i32,
+        dx: i32,
+        dy: i32,
+        wrap: bool,
+    ) -> Option<(i32, i32)> {
+        let (x, y) = (x + dx, y + dy);
+        if wrap {
+            Some((
+                x.rem_euclid(self.width as i32),
+                y.rem_euclid(self.height as i32),
+            ))
+        } else if self.in_bounds(x, y) {
+            Some((x, y))
         } else {
-            CellState::Floor
+            None
         }
     }
 
-    fn num_neighbors(&self, loc: usize) -> usize {
-        let (x, y) = self.as_xy(loc);
-
-        vec![
-            (1, 1),
-            (1, 0),
-            (1, -1),
-            (0, 1),
-            (0, -1),
-            (-1, 1),
-            (-1, 0),
-            (-1, -1),
-        ]
-        .iter()
-        .map(|(dx, dy)| {
-            (self.get_value(x + dx, y + dy) == CellState::OccupiedChair)
-                as usize
-        })
-        .sum()
+    fn seat_at(&self, x: i32, y: i32) -> Option<usize> {
+        let loc = self.as_loc(x, y);
+        if self.states[loc] == CellState::Floor {
+            None
+        } else {
+            Some(loc)
+        }
     }
 
-    fn get_visible_value(
+    // The first seat seen from (x,y) looking along (dx,dy).
+    fn visible_seat(
         &self,
-        mut x: i32,
-        mut y: i32,
+        x: i32,
+        y: i32,
         dx: i32,
         dy: i32,
-    ) -> CellState {
-        while self.in_bounds(x, y) {
-            x += dx;
-            y += dy;
-            let val = self.get_value(x, y);
-            if val != CellState::Floor {
-                return val;
+        wrap: bool,
+    ) -> Option<usize> {
+        // When wrapping, stop once every cell along the line has been
+        // checked.
+        let (mut cx, mut cy) = (x, y);
+        for _ in 0..self.states.len() {
+            let (nx, ny) = self.offset(cx, cy, dx, dy, wrap)?;
+            if (nx, ny) == (x, y) {
+                return None;
+            }
+            cx = nx;
+            cy = ny;
+            if let Some(loc) = self.seat_at(cx, cy) {
+                return Some(loc);
             }
         }
-        CellState::Floor
+        None
     }
 
-    fn num_visible_neighbors(&self, loc: usize) -> usize {
-        let (x, y) = self.as_xy(loc);
-
-        vec![
-            (1, 1),
-            (1, 0),
-            (1, -1),
-            (0, 1),
-            (0, -1),
-            (-1, 1),
-            (-1, 0),
-            (-1, -1),
-        ]
-        .iter()
-        .map(|(dx, dy)| {
-            (self.get_visible_value(x, y, *dx, *dy) == CellState::OccupiedChair)
-                as usize
-        })
-        .sum()
-    }
+    // Finds the neighboring seats of every seat.  Seats never become
+    // floor, so this only needs to be done once per set of rules.
+    fn seat_graph(&self, rules: &Rules) -> SeatGraph {
+        let neighbors = (0..self.states.len())
+            .map(|loc| {
+                if self.states[loc] == CellState::Floor {
+                    return Vec::new();
+                }
 
-    fn next_state_part1(&self, loc: usize) -> CellState {
-        let num_neighbors = self.num_neighbors(loc);
+                let (x, y) = self.as_xy(loc);
+                let offsets = match &rules.neighborhood {
+                    Neighborhood::Adjacent | Neighborhood::LineOfSight => {
+                        &DIRECTIONS[..]
+                    }
+                    Neighborhood::Offsets(offsets) => &offsets[..],
+                };
+                offsets
+                    .iter()
+                    .filter_map(|(dx, dy)| match rules.neighborhood {
+                        Neighborhood::LineOfSight => {
+                            self.visible_seat(x, y, *dx, *dy, rules.wrap)
+                        }
+                        _ => self
+                            .offset(x, y, *dx, *dy, rules.wrap)
+                            .and_then(|(x, y)| self.seat_at(x, y)),
+                    })
+                    .collect()
+            })
+            .collect::<Vec<Vec<usize>>>();
 
-        match self.states[loc] {
-            CellState::Floor => CellState::Floor,
+        let mut dependents = vec![Vec::new(); neighbors.len()];
+        neighbors.iter().enumerate().for_each(|(loc, locs)| {
+            locs.iter()
+                .for_each(|neighbor| dependents[*neighbor].push(loc))
+        });
 
-            CellState::EmptyChair => {
-                if num_neighbors == 0 {
-                    CellState::OccupiedChair
-                } else {
-                    CellState::EmptyChair
-                }
-            }
-
-            CellState::OccupiedChair => {
-                if num_neighbors >= 4 {
-                    CellState::EmptyChair
-                } else {
-                    CellState::OccupiedChair
-                }
-            }
+        SeatGraph {
+            neighbors,
+            dependents,
         }
     }
 
-    fn next_state_part2(&self, loc: usize) -> CellState {
-        let num_neighbors = self.num_visible_neighbors(loc);
+    fn num_neighbors(&self, loc: usize, graph: &SeatGraph) -> usize {
+        graph.neighbors[loc]
+            .iter()
+            .filter(|neighbor| {
+                self.states[**neighbor] == CellState::OccupiedChair
+            })
+            .count()
+    }
 
+    fn next_state(
+        &self,
+        loc: usize,
+        rules: &Rules,
+        graph: &SeatGraph,
+    ) -> CellState {
         match self.states[loc] {
             CellState::Floor => CellState::Floor,
 
             CellState::EmptyChair => {
-                if num_neighbors == 0 {
+                if self.num_neighbors(loc, graph) <= rules.occupy_threshold {
                     CellState::OccupiedChair
                 } else {
                     CellState::EmptyChair
@@ -167,7 +333,7 @@ impl Ferry {
             }
 
             CellState::OccupiedChair => {
-                if num_neighbors >= 5 {
+                if self.num_neighbors(loc, graph) >= rules.vacate_threshold {
                     CellState::EmptyChair
                 } else {
                     CellState::OccupiedChair
@@ -176,33 +342,109 @@ impl Ferry {
         }
     }
 
-    fn iterate_part1(&self) -> Self {
-        let states = self
-            .states
+    // Advances one generation, only re-evaluating the given seats.
+    // Returns the seats that must be checked in the next generation,
+    // which is empty once the ferry is stable.
+    fn iterate(
+        &mut self,
+        rules: &Rules,
+        graph: &SeatGraph,
+        to_check: &[usize],
+    ) -> Vec<usize> {
+        let changed = to_check
             .iter()
-            .enumerate()
-            .map(|(loc, _state)| self.next_state_part1(loc))
-            .collect();
+            .map(|&loc| (loc, self.next_state(loc, rules, graph)))
+            .filter(|&(loc, state)| state != self.states[loc])
+            .collect::<Vec<_>>();
+
+        let mut marked = vec![false; self.states.len()];
+        let mut next_check = Vec::new();
+        changed.into_iter().for_each(|(loc, state)| {
+            self.states[loc] = state;
+            std::iter::once(&loc)
+                .chain(graph.dependents[loc].iter())
+                .for_each(|&dependent| {
+                    if !marked[dependent] {
+                        marked[dependent] = true;
+                        next_check.push(dependent);
+                    }
+                });
+        });
+
+        next_check
+    }
+
+    fn seats(&self) -> Vec<usize> {
+        (0..self.states.len())
+            .filter(|&loc| self.states[loc] != CellState::Floor)
+            .collect()
+    }
 
-        Ferry {
-            height: self.height,
-            width: self.width,
-            states,
+    fn state_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.states.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    // The state after the given number of generations.
+    fn advanced(
+        &self,
+        rules: &Rules,
+        graph: &SeatGraph,
+        generations: usize,
+    ) -> Ferry {
+        let mut ferry = self.clone();
+        let mut to_check = ferry.seats();
+        for _ in 0..generations {
+            to_check = ferry.iterate(rules, graph, &to_check);
         }
+        ferry
     }
 
-    fn iterate_part2(&self) -> Self {
-        let states = self
-            .states
-            .iter()
-            .enumerate()
-            .map(|(loc, _state)| self.next_state_part2(loc))
-            .collect();
+    // Runs until the ferry either stops changing or returns to an
+    // earlier state.  Earlier states are looked up by their hash, so
+    // that the history need not be kept.  A matching hash is confirmed
+    // against the full state, taken from the history if it was kept or
+    // else replayed from the start.
+    fn simulate(mut self, rules: &Rules, keep_history: bool) -> Simulation {
+        let graph = self.seat_graph(rules);
+        let initial = self.clone();
+        let mut to_check = self.seats();
+        let mut seen = HashMap::<u64, Vec<usize>>::new();
+        let mut history = Vec::new();
+        let mut generation = 0;
+
+        let outcome = loop {
+            if keep_history {
+                history.push(self.clone());
+            }
+            let earlier = seen.entry(self.state_hash()).or_default();
+            let repeated = earlier.iter().copied().find(|&start| {
+                let states = match history.get(start) {
+                    Some(ferry) => ferry.states.clone(),
+                    None => initial.advanced(rules, &graph, start).states,
+                };
+                states == self.states
+            });
+            if let Some(start) = repeated {
+                break Outcome::Periodic {
+                    start,
+                    period: generation - start,
+                };
+            }
+            earlier.push(generation);
+
+            to_check = self.iterate(rules, &graph, &to_check);
+            if to_check.is_empty() {
+                break Outcome::Stable { generation };
+            }
+            generation += 1;
+        };
 
-        Ferry {
-            height: self.height,
-            width: self.width,
-            states,
+        Simulation {
+            outcome,
+            final_state: self,
+            history,
         }
     }
 
@@ -214,6 +456,21 @@ impl Ferry {
     }
 }
 
+impl Ferry {
+    fn frame(&self) -> util::Frame {
+        let mut frame = util::Frame::new(self.width, self.height, [0, 0, 0]);
+        self.states.iter().enumerate().for_each(|(loc, state)| {
+            let color = match state {
+                CellState::OccupiedChair => [200, 40, 40],
+                CellState::EmptyChair => [40, 160, 40],
+                CellState::Floor => [32, 32, 32],
+            };
+            frame.set(loc % self.width, loc / self.width, color);
+        });
+        frame
+    }
+}
+
 impl std::fmt::Display for Ferry {
     fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
         self.states
@@ -238,31 +495,68 @@ impl std::fmt::Display for Ferry {
     }
 }
 
+fn report(
+    label: &str,
+    simulation: &Simulation,
+    print_history: bool,
+    frame_output: &Option<util::FrameOutput>,
+) -> Result<(), util::Error> {
+    match simulation.outcome {
+        Outcome::Stable { generation } => println!(
+            "Stable number of seated, {}: {} (stable from generation {})",
+            label,
+            simulation.final_state.num_occupied(),
+            generation
+        ),
+        Outcome::Periodic { start, period } => println!(
+            "No stable state, {}: period {} from generation {}",
+            label, period, start
+        ),
+    }
+
+    if print_history {
+        simulation.history.iter().enumerate().for_each(
+            |(generation, state)| {
+                println!("Generation {}:\n{}", generation, state)
+            },
+        );
+    }
+
+    if let Some(frame_output) = frame_output {
+        let frame_output = frame_output.with_suffix(label);
+        simulation.history.iter().enumerate().try_for_each(
+            |(generation, state)| {
+                frame_output.write(
+                    generation,
+                    &state.to_string(),
+                    &state.frame(),
+                )
+            },
+        )?;
+    }
+
+    Ok(())
+}
+
 fn main() -> Result<(), util::Error> {
     let args: Vec<String> = std::env::args().collect();
     let filename = &args[1];
+    let print_history = util::has_flag(&args, "history");
+    let frame_output = util::FrameOutput::from_args(&args)?;
+    let keep_history = print_history || frame_output.is_some();
 
-    let mut state = Ferry::parse(filename)?;
-    loop {
-        let next_state = state.iterate_part1();
-        if state == next_state {
-            break;
-        }
-        state = next_state;
-    }
+    let simulation =
+        Ferry::parse(filename)?.simulate(&Rules::part1(), keep_history);
+    report("p1", &simulation, print_history, &frame_output)?;
 
-    println!("Stable number of seated, p1: {}", state.num_occupied());
+    let simulation =
+        Ferry::parse(filename)?.simulate(&Rules::part2(), keep_history);
+    report("p2", &simulation, print_history, &frame_output)?;
 
-    let mut state = Ferry::parse(filename)?;
-    loop {
-        let next_state = state.iterate_part2();
-        if state == next_state {
-            break;
-        }
-        state = next_state;
+    if let Some(rules) = Rules::from_args(&args, Rules::part2())? {
+        let simulation = Ferry::parse(filename)?.simulate(&rules, keep_history);
+        report("custom", &simulation, print_history, &frame_output)?;
     }
 
-    println!("Stable number of seated, p2: {}", state.num_occupied());
-
     Ok(())
 }
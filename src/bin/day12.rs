@@ -1,4 +1,4 @@
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct BoatState {
     x: i32,
     y: i32,
@@ -76,10 +76,26 @@ impl BoatState {
                     facing: new_facing,
                 }
             }
+
+            Command::Rotate(degrees) => (0..quarter_turns(degrees)).fold(
+                BoatState {
+                    x: self.x,
+                    y: self.y,
+                    facing: self.facing,
+                },
+                |boat, _| boat.apply_command(Command::RotateLeft),
+            ),
         }
     }
 }
 
+// Number of counter-clockwise quarter turns, from 0 to 3, closest to
+// the given angle.  Only the floating-point models support angles
+// that are not multiples of 90.
+fn quarter_turns(degrees: f64) -> i32 {
+    ((degrees / 90.0).round() as i64).rem_euclid(4) as i32
+}
+
 #[derive(Debug, Copy, Clone)]
 enum Direction {
     North,
@@ -88,6 +104,18 @@ enum Direction {
     West,
 }
 
+impl Direction {
+    // Counter-clockwise angle from east, in degrees.
+    fn degrees(&self) -> f64 {
+        match self {
+            Direction::East => 0.0,
+            Direction::North => 90.0,
+            Direction::West => 180.0,
+            Direction::South => 270.0,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 enum Command {
     Move(Direction, i32),
@@ -95,39 +123,71 @@ enum Command {
     RotateLeft,
     RotateRight,
     TurnAround,
+    // Counter-clockwise rotation in degrees, for any rotation that
+    // isn't a single left, right, or about-face.
+    Rotate(f64),
 }
 
-impl std::str::FromStr for Command {
-    type Err = util::Error;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
+impl Command {
+    // Parses a command, allowing rotations that are not a multiple of
+    // 90 degrees only if arbitrary_angles is set.
+    fn parse(s: &str, arbitrary_angles: bool) -> Result<Self, util::Error> {
         let mut chars = s.chars();
         let c = chars.next().ok_or(util::Error::NoneError)?;
-        let amount = chars.as_str().parse::<i32>()?;
+        let amount = chars.as_str();
+
+        let rotation = |degrees: f64| {
+            if degrees % 90.0 == 0.0 {
+                Ok(match quarter_turns(degrees) {
+                    1 => Command::RotateLeft,
+                    2 => Command::TurnAround,
+                    3 => Command::RotateRight,
+                    _ => Command::Rotate(0.0),
+                })
+            } else if arbitrary_angles && degrees.is_finite() {
+                Ok(Command::Rotate(degrees))
+            } else {
+                Err(util::Error::InvalidValue(s.to_owned()))
+            }
+        };
+        let degrees = || {
+            amount
+                .parse::<f64>()
+                .map_err(|_| util::Error::InvalidValue(s.to_owned()))
+        };
 
         match c {
-            'N' => Ok(Command::Move(Direction::North, amount)),
-            'S' => Ok(Command::Move(Direction::South, amount)),
-            'E' => Ok(Command::Move(Direction::East, amount)),
-            'W' => Ok(Command::Move(Direction::West, amount)),
-            'F' => Ok(Command::Forward(amount)),
-            'L' => match amount {
-                90 => Ok(Command::RotateLeft),
-                180 => Ok(Command::TurnAround),
-                270 => Ok(Command::RotateRight),
-                _ => Err(util::Error::InvalidValue(s.to_owned())),
-            },
-            'R' => match amount {
-                90 => Ok(Command::RotateRight),
-                180 => Ok(Command::TurnAround),
-                270 => Ok(Command::RotateLeft),
-                _ => Err(util::Error::InvalidValue(s.to_owned())),
-            },
+            'N' => Ok(Command::Move(Direction::North, amount.parse()?)),
+            'S' => Ok(Command::Move(Direction::South, amount.parse()?)),
+            'E' => Ok(Command::Move(Direction::East, amount.parse()?)),
+            'W' => Ok(Command::Move(Direction::West, amount.parse()?)),
+            'F' => Ok(Command::Forward(amount.parse()?)),
+            'L' => rotation(degrees()?),
+            'R' => rotation(-degrees()?),
             _ => Err(util::Error::InvalidValue(s.to_owned())),
         }
     }
+
+    // Counter-clockwise rotation in degrees, if this is a rotation.
+    fn rotation_degrees(&self) -> Option<f64> {
+        match self {
+            Command::RotateLeft => Some(90.0),
+            Command::RotateRight => Some(-90.0),
+            Command::TurnAround => Some(180.0),
+            Command::Rotate(degrees) => Some(*degrees),
+            _ => None,
+        }
+    }
 }
 
-#[derive(Debug)]
+impl std::str::FromStr for Command {
+    type Err = util::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Command::parse(s, false)
+    }
+}
+
+#[derive(Debug, Clone)]
 struct WayPoint {
     waypoint_x: i32,
     waypoint_y: i32,
@@ -175,20 +235,563 @@ impl WayPoint {
                 self.waypoint_x = temp.0;
                 self.waypoint_y = temp.1;
             }
+
+            Command::Rotate(degrees) => {
+                (0..quarter_turns(degrees))
+                    .for_each(|_| self.apply_command(Command::RotateLeft));
+            }
+        }
+    }
+}
+
+fn rotate_vector(x: f64, y: f64, degrees: f64) -> (f64, f64) {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    (x * cos - y * sin, x * sin + y * cos)
+}
+
+// Equivalent of BoatState, with a heading that may point in any
+// direction.
+#[derive(Debug, Clone)]
+struct FloatBoatState {
+    x: f64,
+    y: f64,
+    heading: f64,
+}
+
+impl FloatBoatState {
+    fn apply_command(&mut self, c: Command) {
+        match c {
+            Command::Move(dir, dist) => {
+                let (dx, dy) = rotate_vector(dist as f64, 0.0, dir.degrees());
+                self.x += dx;
+                self.y += dy;
+            }
+
+            Command::Forward(dist) => {
+                let (dx, dy) = rotate_vector(dist as f64, 0.0, self.heading);
+                self.x += dx;
+                self.y += dy;
+            }
+
+            _ => {
+                let degrees = c.rotation_degrees().unwrap();
+                self.heading = (self.heading + degrees).rem_euclid(360.0);
+            }
+        }
+    }
+}
+
+// Equivalent of WayPoint, where the waypoint may be rotated by any
+// angle.
+#[derive(Debug, Clone)]
+struct FloatWayPoint {
+    waypoint_x: f64,
+    waypoint_y: f64,
+    boat_x: f64,
+    boat_y: f64,
+}
+
+impl FloatWayPoint {
+    fn apply_command(&mut self, c: Command) {
+        match c {
+            Command::Move(dir, dist) => {
+                let (dx, dy) = rotate_vector(dist as f64, 0.0, dir.degrees());
+                self.waypoint_x += dx;
+                self.waypoint_y += dy;
+            }
+
+            Command::Forward(num) => {
+                self.boat_x += num as f64 * self.waypoint_x;
+                self.boat_y += num as f64 * self.waypoint_y;
+            }
+
+            _ => {
+                let degrees = c.rotation_degrees().unwrap();
+                let (x, y) =
+                    rotate_vector(self.waypoint_x, self.waypoint_y, degrees);
+                self.waypoint_x = x;
+                self.waypoint_y = y;
+            }
+        }
+    }
+}
+
+fn vector_heading(x: f64, y: f64) -> f64 {
+    y.atan2(x).to_degrees().rem_euclid(360.0)
+}
+
+// Common interface to each model of the ship, for recording routes.
+trait Navigator {
+    fn navigate(&mut self, c: Command);
+    fn ship(&self) -> (f64, f64);
+    // Direction in which a forward command would move the ship, in
+    // degrees counter-clockwise from east.
+    fn heading(&self) -> f64;
+    // Absolute position of the waypoint, for models that have one.
+    fn waypoint(&self) -> Option<(f64, f64)> {
+        None
+    }
+}
+
+impl Navigator for BoatState {
+    fn navigate(&mut self, c: Command) {
+        *self = self.apply_command(c);
+    }
+
+    fn ship(&self) -> (f64, f64) {
+        (self.x as f64, self.y as f64)
+    }
+
+    fn heading(&self) -> f64 {
+        self.facing.degrees()
+    }
+}
+
+impl Navigator for WayPoint {
+    fn navigate(&mut self, c: Command) {
+        self.apply_command(c);
+    }
+
+    fn ship(&self) -> (f64, f64) {
+        (self.boat_x as f64, self.boat_y as f64)
+    }
+
+    fn heading(&self) -> f64 {
+        vector_heading(self.waypoint_x as f64, self.waypoint_y as f64)
+    }
+
+    fn waypoint(&self) -> Option<(f64, f64)> {
+        Some((
+            (self.boat_x + self.waypoint_x) as f64,
+            (self.boat_y + self.waypoint_y) as f64,
+        ))
+    }
+}
+
+impl Navigator for FloatBoatState {
+    fn navigate(&mut self, c: Command) {
+        self.apply_command(c);
+    }
+
+    fn ship(&self) -> (f64, f64) {
+        (self.x, self.y)
+    }
+
+    fn heading(&self) -> f64 {
+        self.heading
+    }
+}
+
+impl Navigator for FloatWayPoint {
+    fn navigate(&mut self, c: Command) {
+        self.apply_command(c);
+    }
+
+    fn ship(&self) -> (f64, f64) {
+        (self.boat_x, self.boat_y)
+    }
+
+    fn heading(&self) -> f64 {
+        vector_heading(self.waypoint_x, self.waypoint_y)
+    }
+
+    fn waypoint(&self) -> Option<(f64, f64)> {
+        Some((self.boat_x + self.waypoint_x, self.boat_y + self.waypoint_y))
+    }
+}
+
+// Positions before the first command and after each command.
+#[derive(Debug)]
+struct Trajectory {
+    ship: Vec<(f64, f64)>,
+    waypoint: Option<Vec<(f64, f64)>>,
+    // Heading of the ship while executing each command.
+    headings: Vec<f64>,
+}
+
+impl Trajectory {
+    fn record<N: Navigator>(navigator: &mut N, commands: &[Command]) -> Self {
+        let mut ship = vec![navigator.ship()];
+        let mut waypoint = navigator.waypoint().map(|pos| vec![pos]);
+        let mut headings = Vec::new();
+        commands.iter().for_each(|c| {
+            headings.push(navigator.heading());
+            navigator.navigate(*c);
+            ship.push(navigator.ship());
+            if let (Some(waypoint), Some(pos)) =
+                (&mut waypoint, navigator.waypoint())
+            {
+                waypoint.push(pos);
+            }
+        });
+        Self {
+            ship,
+            waypoint,
+            headings,
+        }
+    }
+
+    fn stats(&self) -> RouteStats {
+        let farthest = self.ship.iter().copied().fold(
+            (0.0, 0.0),
+            |best: (f64, f64), pos| {
+                if pos.0.abs() + pos.1.abs() > best.0.abs() + best.1.abs() {
+                    pos
+                } else {
+                    best
+                }
+            },
+        );
+
+        let segment_lengths = self
+            .ship
+            .windows(2)
+            .map(|w| (w[1].0 - w[0].0).hypot(w[1].1 - w[0].1))
+            .collect::<Vec<_>>();
+
+        let mut per_heading = Vec::<(f64, f64)>::new();
+        self.headings
+            .iter()
+            .zip(segment_lengths.iter())
+            .filter(|(_heading, &length)| length > 0.0)
+            .for_each(|(&heading, &length)| {
+                match per_heading.iter_mut().find(|(h, _)| *h == heading) {
+                    Some((_, total)) => *total += length,
+                    None => per_heading.push((heading, length)),
+                }
+            });
+        per_heading.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        RouteStats {
+            farthest,
+            total_distance: segment_lengths.iter().sum(),
+            per_heading,
+            self_intersection: self.self_intersection(),
+        }
+    }
+
+    // Returns the indices of the first pair of commands whose
+    // movements cross or overlap, other than consecutive movements
+    // meeting where one ends and the next begins.
+    fn self_intersection(&self) -> Option<(usize, usize)> {
+        let segments = self
+            .ship
+            .windows(2)
+            .enumerate()
+            .filter(|(_i, w)| w[0] != w[1])
+            .map(|(i, w)| (i, w[0], w[1]))
+            .collect::<Vec<_>>();
+
+        segments.iter().enumerate().find_map(|(a, &(i, p1, p2))| {
+            segments[a + 1..]
+                .iter()
+                .enumerate()
+                .find(|(b, &(_j, q1, q2))| {
+                    if *b == 0 && p2 == q1 {
+                        // Consecutive movements only intersect if the
+                        // second doubles back along the first.
+                        cross(p1, p2, q2) == 0.0
+                            && (p2.0 - p1.0) * (q2.0 - q1.0)
+                                + (p2.1 - p1.1) * (q2.1 - q1.1)
+                                < 0.0
+                    } else {
+                        segments_intersect(p1, p2, q1, q2)
+                    }
+                })
+                .map(|(_b, &(j, _q1, _q2))| (i, j))
+        })
+    }
+
+    fn bounds(&self) -> ((f64, f64), (f64, f64)) {
+        self.ship.iter().chain(self.waypoint.iter().flatten()).fold(
+            (
+                (f64::INFINITY, f64::INFINITY),
+                (f64::NEG_INFINITY, f64::NEG_INFINITY),
+            ),
+            |(min, max), &(x, y)| {
+                ((min.0.min(x), min.1.min(y)), (max.0.max(x), max.1.max(y)))
+            },
+        )
+    }
+
+    // Draws the route with north at the top of the image.
+    fn to_svg(&self) -> String {
+        let ((min_x, min_y), (max_x, max_y)) = self.bounds();
+        let margin = 0.05 * (max_x - min_x).max(max_y - min_y).max(1.0);
+        let marker_size = margin / 2.0;
+
+        let points = |positions: &[(f64, f64)]| {
+            positions
+                .iter()
+                .map(|(x, y)| format!("{},{}", x, 0.0 - y))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+
+        let mut output = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">\n",
+            min_x - margin,
+            -max_y - margin,
+            max_x - min_x + 2.0 * margin,
+            max_y - min_y + 2.0 * margin
+        );
+        output += &format!(
+            "  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" \
+             fill=\"none\" stroke=\"gray\" stroke-dasharray=\"4\" \
+             vector-effect=\"non-scaling-stroke\"/>\n",
+            min_x,
+            -max_y,
+            max_x - min_x,
+            max_y - min_y
+        );
+        if let Some(waypoint) = &self.waypoint {
+            output += &format!(
+                "  <polyline points=\"{}\" fill=\"none\" stroke=\"orange\" \
+                 vector-effect=\"non-scaling-stroke\"/>\n",
+                points(waypoint)
+            );
+        }
+        output += &format!(
+            "  <polyline points=\"{}\" fill=\"none\" stroke=\"blue\" \
+             stroke-width=\"2\" vector-effect=\"non-scaling-stroke\"/>\n",
+            points(&self.ship)
+        );
+
+        let start = self.ship[0];
+        let end = self.ship[self.ship.len() - 1];
+        output += &format!(
+            "  <circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"green\"/>\n",
+            start.0,
+            0.0 - start.1,
+            marker_size
+        );
+        output += &format!(
+            "  <circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"red\"/>\n",
+            end.0,
+            0.0 - end.1,
+            marker_size
+        );
+        output += "</svg>\n";
+        output
+    }
+}
+
+#[derive(Debug)]
+struct RouteStats {
+    // Position with the largest Manhattan distance from the origin.
+    farthest: (f64, f64),
+    total_distance: f64,
+    // Distance travelled, and so time spent at a constant speed,
+    // under each heading in degrees.
+    per_heading: Vec<(f64, f64)>,
+    // Indices of two commands whose movements cross.
+    self_intersection: Option<(usize, usize)>,
+}
+
+impl std::fmt::Display for RouteStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(
+            f,
+            "\tFarthest point = ({:.3}, {:.3}), Manhattan={:.3}",
+            self.farthest.0,
+            self.farthest.1,
+            self.farthest.0.abs() + self.farthest.1.abs()
+        )?;
+        writeln!(f, "\tTotal distance travelled = {:.3}", self.total_distance)?;
+        for (heading, distance) in &self.per_heading {
+            writeln!(f, "\tHeading {:.3}: {:.3}", heading, distance)?;
+        }
+        match self.self_intersection {
+            Some((i, j)) => writeln!(
+                f,
+                "\tRoute self-intersects at commands {} and {}",
+                i, j
+            ),
+            None => writeln!(f, "\tRoute does not self-intersect"),
+        }
+    }
+}
+
+// Positive if r is counter-clockwise of the line from p to q, negative
+// if clockwise, and zero if collinear.
+fn cross(p: (f64, f64), q: (f64, f64), r: (f64, f64)) -> f64 {
+    (q.0 - p.0) * (r.1 - p.1) - (q.1 - p.1) * (r.0 - p.0)
+}
+
+fn segments_intersect(
+    p1: (f64, f64),
+    p2: (f64, f64),
+    q1: (f64, f64),
+    q2: (f64, f64),
+) -> bool {
+    let on_segment = |a: (f64, f64), b: (f64, f64), c: (f64, f64)| {
+        c.0 >= a.0.min(b.0)
+            && c.0 <= a.0.max(b.0)
+            && c.1 >= a.1.min(b.1)
+            && c.1 <= a.1.max(b.1)
+    };
+
+    let d1 = cross(q1, q2, p1);
+    let d2 = cross(q1, q2, p2);
+    let d3 = cross(p1, p2, q1);
+    let d4 = cross(p1, p2, q2);
+
+    if d1 * d2 < 0.0 && d3 * d4 < 0.0 {
+        return true;
+    }
+
+    (d1 == 0.0 && on_segment(q1, q2, p1))
+        || (d2 == 0.0 && on_segment(q1, q2, p2))
+        || (d3 == 0.0 && on_segment(p1, p2, q1))
+        || (d4 == 0.0 && on_segment(p1, p2, q2))
+}
+
+// Commands that, starting from the final state of the given commands,
+// return to the initial state by retracing the route backwards.
+fn inverse_commands(commands: &[Command]) -> Vec<Command> {
+    commands
+        .iter()
+        .rev()
+        .flat_map(|c| match c {
+            Command::Move(dir, dist) => {
+                let opposite = match dir {
+                    Direction::North => Direction::South,
+                    Direction::South => Direction::North,
+                    Direction::East => Direction::West,
+                    Direction::West => Direction::East,
+                };
+                vec![Command::Move(opposite, *dist)]
+            }
+            // Turning around reverses both the ship's heading and the
+            // waypoint, so moving forward between two about-faces
+            // undoes a forward movement in either model.
+            Command::Forward(dist) => vec![
+                Command::TurnAround,
+                Command::Forward(*dist),
+                Command::TurnAround,
+            ],
+            Command::RotateLeft => vec![Command::RotateRight],
+            Command::RotateRight => vec![Command::RotateLeft],
+            Command::TurnAround => vec![Command::TurnAround],
+            Command::Rotate(degrees) => vec![Command::Rotate(-degrees)],
+        })
+        .collect()
+}
+
+impl std::fmt::Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Command::Move(Direction::North, dist) => write!(f, "N{}", dist),
+            Command::Move(Direction::South, dist) => write!(f, "S{}", dist),
+            Command::Move(Direction::East, dist) => write!(f, "E{}", dist),
+            Command::Move(Direction::West, dist) => write!(f, "W{}", dist),
+            Command::Forward(dist) => write!(f, "F{}", dist),
+            Command::RotateLeft => write!(f, "L90"),
+            Command::RotateRight => write!(f, "R90"),
+            Command::TurnAround => write!(f, "L180"),
+            Command::Rotate(degrees) => write!(f, "L{}", degrees),
         }
     }
 }
 
+// Prints statistics and writes any requested output for one route.
+fn report_route<N: Navigator + Clone>(
+    args: &[String],
+    part: &str,
+    navigator: &mut N,
+    commands: &[Command],
+) -> Result<(), util::Error> {
+    let trajectory = Trajectory::record(navigator, commands);
+    write_svg(args, part, &trajectory)?;
+
+    if util::has_flag(args, "stats") {
+        print!("Part {} route:\n{}", part, trajectory.stats());
+    }
+
+    if util::has_flag(args, "inverse") {
+        let inverse = inverse_commands(commands);
+        let reverse_trajectory =
+            Trajectory::record(&mut navigator.clone(), &inverse);
+        let end = reverse_trajectory.ship[reverse_trajectory.ship.len() - 1];
+        println!(
+            "Part {} reverse route of {} commands ends at ({:.3}, {:.3}):",
+            part,
+            inverse.len(),
+            end.0,
+            end.1
+        );
+        println!(
+            "{}",
+            inverse
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+        write_svg(args, &format!("{}_inverse", part), &reverse_trajectory)?;
+    }
+
+    Ok(())
+}
+
+fn write_svg(
+    args: &[String],
+    part: &str,
+    trajectory: &Trajectory,
+) -> Result<(), util::Error> {
+    if let Some(prefix) = util::arg_value(args, "svg") {
+        std::fs::write(
+            format!("{}_{}.svg", prefix, part),
+            trajectory.to_svg(),
+        )?;
+    }
+    Ok(())
+}
+
 fn main() -> Result<(), util::Error> {
     let args: Vec<String> = std::env::args().collect();
     let filename = &args[1];
 
+    let arbitrary_angles = util::has_flag(&args, "angles");
+
     let commands = std::fs::read_to_string(filename)?
         .lines()
         .filter(|line| !line.is_empty())
-        .map(|line| line.parse::<Command>())
+        .map(|line| Command::parse(line, arbitrary_angles))
         .collect::<Result<Vec<_>, _>>()?;
 
+    if arbitrary_angles {
+        let mut boat = FloatBoatState {
+            x: 0.0,
+            y: 0.0,
+            heading: Direction::East.degrees(),
+        };
+        report_route(&args, "a", &mut boat, &commands)?;
+        println!(
+            "Part a, pos = ({:.3}, {:.3}), heading = {:.3}, Manhattan={:.3}",
+            boat.x,
+            boat.y,
+            boat.heading,
+            boat.x.abs() + boat.y.abs()
+        );
+
+        let mut waypoint = FloatWayPoint {
+            waypoint_x: 10.0,
+            waypoint_y: 1.0,
+            boat_x: 0.0,
+            boat_y: 0.0,
+        };
+        report_route(&args, "b", &mut waypoint, &commands)?;
+        println!(
+            "Part b, pos = ({:.3}, {:.3}), Manhattan={:.3}",
+            waypoint.boat_x,
+            waypoint.boat_y,
+            waypoint.boat_x.abs() + waypoint.boat_y.abs()
+        );
+
+        return Ok(());
+    }
+
     let initial = BoatState {
         x: 0,
         y: 0,
@@ -196,9 +799,8 @@ fn main() -> Result<(), util::Error> {
     };
 
     println!("Initial = {:?}", initial);
-    let final_pos = commands
-        .iter()
-        .fold(initial, |boat, command| boat.apply_command(*command));
+    let mut final_pos = initial;
+    report_route(&args, "a", &mut final_pos, &commands)?;
 
     println!(
         "Part a, pos = ({}, {}), Manhattan={}",
@@ -213,7 +815,7 @@ fn main() -> Result<(), util::Error> {
         boat_x: 0,
         boat_y: 0,
     };
-    commands.iter().for_each(|c| waypoint.apply_command(*c));
+    report_route(&args, "b", &mut waypoint, &commands)?;
     println!(
         "Part b, pos = ({}, {}), Manhattan={}",
         waypoint.boat_x,
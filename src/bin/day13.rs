@@ -1,22 +1,14 @@
-fn part_1(lines: &[String]) -> Result<(), util::Error> {
-    let start_time = lines[0].parse::<i64>()?;
-    let bus_times = lines[1]
-        .split(',')
-        .map(|s| s.parse::<i64>())
-        .filter(|x| x.is_ok())
-        .collect::<Result<Vec<_>, _>>()?;
+use itertools::Itertools;
 
-    let next_arrival_time = |period: i64| {
-        let num_periods_passed =
-            ((start_time as f64) / (period as f64)).ceil() as i64;
-        period * num_periods_passed
-    };
+fn part_1(lines: &[String], args: &[String]) -> Result<(), util::Error> {
+    let start_time = lines[0].parse::<i128>()?;
+    let schedule = BusSchedule::parse(&lines[1])?;
 
-    let next_bus = bus_times
+    let wait_times = schedule.wait_times(start_time);
+    let (next_bus, wait_time) = wait_times
         .iter()
-        .min_by_key(|x| next_arrival_time(**x))
-        .unwrap();
-    let wait_time = next_arrival_time(*next_bus) - start_time;
+        .min_by_key(|(_bus, wait)| *wait)
+        .ok_or(util::Error::NoneError)?;
 
     println!(
         "Next bus = {}, wait time = {}, Part a = {}",
@@ -25,37 +17,207 @@ fn part_1(lines: &[String]) -> Result<(), util::Error> {
         next_bus * wait_time
     );
 
+    if util::has_flag(args, "waits") {
+        wait_times
+            .iter()
+            .for_each(|(bus, wait)| println!("\tBus {}: {}", bus, wait));
+    }
+
+    if let Some(end_time) = util::arg_value(args, "until") {
+        let end_time = end_time.parse::<i128>()?;
+        let departures = schedule.departures(start_time, end_time);
+        departures
+            .iter()
+            .for_each(|(time, bus)| println!("\t{}: bus {}", time, bus));
+        match schedule.longest_gap(start_time, end_time) {
+            Some((from, to)) => println!(
+                "Longest gap without departures = {} ({} to {})",
+                to - from,
+                from,
+                to
+            ),
+            None => println!("Fewer than two departures in window"),
+        }
+    }
+
     Ok(())
 }
 
-fn part_2(lines: &[String]) -> Result<(), util::Error> {
-    let bus_offsets: Vec<_> = lines[1]
-        .split(',')
-        .enumerate()
-        .filter(|(_i, s)| *s != "x")
-        .map(|(i, s)| (i as i64, s.parse::<i64>().unwrap()))
-        .collect();
+// Returns (g, x, y) such that a*x + b*y = g = gcd(a,b).
+fn extended_gcd(a: i128, b: i128) -> (i128, i128, i128) {
+    if b == 0 {
+        (a, 1, 0)
+    } else {
+        let (g, x, y) = extended_gcd(b, a.rem_euclid(b));
+        (g, y, x - a.div_euclid(b) * y)
+    }
+}
 
-    let required_offset = bus_offsets.iter().fold(
-        (0, 1),
-        |(prev_offset, prev_period), (offset, period)| {
-            let offset = *offset;
-            let period = *period;
-
-            let additional_cycles = (0..period)
-                .find(|p| {
-                    (prev_offset + p * prev_period + offset) % period == 0
-                })
-                .unwrap();
-
-            (
-                additional_cycles * prev_period + prev_offset,
-                period * prev_period,
-            )
-        },
-    );
+// A bus that departs every period minutes, and that should depart
+// offset minutes after the timestamp searched for in part 2.
+#[derive(Debug, Clone, Copy)]
+struct Bus {
+    period: i128,
+    offset: i128,
+}
+
+impl Bus {
+    // Parses "ID", or "ID@OFFSET" to override the default offset given
+    // by the position in the list.
+    fn parse(s: &str, index: usize) -> Result<Self, util::Error> {
+        let (period, offset) = match s.find('@') {
+            Some(at) => (s[..at].parse()?, s[at + 1..].parse()?),
+            None => (s.parse()?, index as i128),
+        };
+        if period <= 0 {
+            return Err(util::Error::InvalidValue(s.to_owned()));
+        }
+        Ok(Self { period, offset })
+    }
+
+    // The first departure at or after the given time.
+    fn next_departure(&self, time: i128) -> i128 {
+        time + (-time).rem_euclid(self.period)
+    }
+}
+
+#[derive(Debug)]
+struct BusSchedule {
+    buses: Vec<Bus>,
+}
+
+impl BusSchedule {
+    fn parse(line: &str) -> Result<Self, util::Error> {
+        let buses = line
+            .split(',')
+            .enumerate()
+            .filter(|(_i, s)| *s != "x")
+            .map(|(i, s)| Bus::parse(s, i))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { buses })
+    }
+
+    // The time until each bus next departs, as (bus, wait) pairs.
+    fn wait_times(&self, time: i128) -> Vec<(i128, i128)> {
+        self.buses
+            .iter()
+            .map(|bus| (bus.period, bus.next_departure(time) - time))
+            .collect()
+    }
+
+    // Every departure in [start, end), as (time, bus) pairs sorted by
+    // time.
+    fn departures(&self, start: i128, end: i128) -> Vec<(i128, i128)> {
+        let mut departures: Vec<_> = self
+            .buses
+            .iter()
+            .flat_map(|bus| {
+                let first = bus.next_departure(start);
+                (0..)
+                    .map(move |i| first + i * bus.period)
+                    .take_while(move |time| *time < end)
+                    .map(move |time| (time, bus.period))
+            })
+            .collect();
+        departures.sort_unstable();
+        departures
+    }
+
+    // The consecutive pair of departure times in [start, end) that are
+    // furthest apart.
+    fn longest_gap(&self, start: i128, end: i128) -> Option<(i128, i128)> {
+        self.departures(start, end)
+            .iter()
+            .map(|(time, _bus)| *time)
+            .tuple_windows()
+            .max_by_key(|(from, to)| to - from)
+    }
+
+    // Combines every constraint into a single timestamp and period,
+    // such that the valid timestamps are first + n*period.  Periods
+    // need not be coprime, and None is returned if the constraints
+    // conflict.
+    fn solve(&self) -> Result<Option<(i128, i128)>, util::Error> {
+        let overflow = || util::Error::InvalidValue("Overflow".to_string());
+
+        let mut combined = (0, 1);
+        for &Bus { offset, period } in &self.buses {
+            let (prev_first, prev_period) = combined;
+            let target = (-offset).rem_euclid(period);
+
+            let (g, inv, _) = extended_gcd(prev_period, period);
+            let diff = target - prev_first;
+            if diff.rem_euclid(g) != 0 {
+                return Ok(None);
+            }
+
+            // Solve prev_first + k*prev_period = target (mod period)
+            let reduced_period = period / g;
+            let k = (diff / g)
+                .rem_euclid(reduced_period)
+                .checked_mul(inv.rem_euclid(reduced_period))
+                .ok_or_else(overflow)?
+                .rem_euclid(reduced_period);
+
+            let new_period = prev_period
+                .checked_mul(reduced_period)
+                .ok_or_else(overflow)?;
+            let new_first = k
+                .checked_mul(prev_period)
+                .and_then(|val| val.checked_add(prev_first))
+                .ok_or_else(overflow)?
+                .rem_euclid(new_period);
+            combined = (new_first, new_period);
+        }
+
+        Ok(Some(combined))
+    }
+
+    // The first num_timestamps valid timestamps at or after start.
+    fn next_timestamps(
+        &self,
+        start: i128,
+        num_timestamps: usize,
+    ) -> Result<Vec<i128>, util::Error> {
+        Ok(match self.solve()? {
+            None => Vec::new(),
+            Some((first, period)) => {
+                let after_start =
+                    first + (start - first).div_euclid(period) * period;
+                let after_start = if after_start < start {
+                    after_start + period
+                } else {
+                    after_start
+                };
+                (0..num_timestamps as i128)
+                    .map(|i| after_start + i * period)
+                    .collect()
+            }
+        })
+    }
+}
+
+fn part_2(lines: &[String], args: &[String]) -> Result<(), util::Error> {
+    let schedule = BusSchedule::parse(&lines[1])?;
+
+    match schedule.solve()? {
+        Some((first, period)) => {
+            println!("Part b, moderate force = {}", first);
+            println!("Part b, repeats every {}", period);
+        }
+        None => println!("Part b, no timestamp satisfies every bus"),
+    }
 
-    println!("Part b, moderate force = {}", required_offset.0);
+    if let Some(num) = util::arg_value(args, "count") {
+        let start = util::arg_value(args, "after")
+            .map(|val| val.parse::<i128>())
+            .transpose()?
+            .unwrap_or(0);
+        schedule
+            .next_timestamps(start, num.parse::<usize>()?)?
+            .iter()
+            .for_each(|timestamp| println!("\t{}", timestamp));
+    }
 
     Ok(())
 }
@@ -91,8 +253,8 @@ fn main() -> Result<(), util::Error> {
         .map(|s| s.to_owned())
         .collect();
 
-    part_1(&lines)?;
-    part_2(&lines)?;
+    part_1(&lines, &args)?;
+    part_2(&lines, &args)?;
     //part_2_brute_force(&lines)?;
 
     Ok(())
@@ -8,40 +8,70 @@ struct Mask {
     mask_x: u64,
 }
 
+impl Mask {
+    fn describe(&self, width: u32) -> String {
+        (0..width)
+            .rev()
+            .map(|b| 1u64 << b)
+            .map(|bit| match () {
+                _ if self.mask_0 & bit > 0 => '0',
+                _ if self.mask_1 & bit > 0 => '1',
+                _ => 'X',
+            })
+            .collect()
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 enum Command {
     Mask(Mask),
     Memset { location: u64, value: u64 },
 }
 
-impl std::str::FromStr for Command {
-    type Err = util::Error;
+const DEFAULT_WIDTH: u32 = 36;
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.starts_with("mask") {
-            let mask_str = s.split_whitespace().last().unwrap();
-            let mask_0 = mask_str
-                .chars()
-                .fold(0u64, |acc, c| 2 * acc + ((c == '0') as u64));
-
-            let mask_1 = mask_str
-                .chars()
-                .fold(0u64, |acc, c| 2 * acc + ((c == '1') as u64));
-
-            let mask_x = mask_str
-                .chars()
-                .fold(0u64, |acc, c| 2 * acc + ((c == 'X') as u64));
-
-            Ok(Command::Mask(Mask {
-                mask_0,
-                mask_1,
-                mask_x,
-            }))
-        } else if s.starts_with("mem") {
-            let components: Vec<_> =
-                s.split(|c| c == '[' || c == ']' || c == ' ').collect();
-            let location = components[1].parse::<u64>()?;
-            let value = components[4].parse::<u64>()?;
+impl Command {
+    // Parses a command for a machine whose words are width bits wide.
+    fn parse(s: &str, width: u32) -> Result<Self, util::Error> {
+        if let Some(mask_str) = s.strip_prefix("mask = ") {
+            if mask_str.len() != width as usize {
+                return Err(util::Error::InvalidValue(format!(
+                    "Mask {} has {} bits, expected {}",
+                    mask_str,
+                    mask_str.len(),
+                    width
+                )));
+            }
+
+            let mut mask = Mask {
+                mask_0: 0,
+                mask_1: 0,
+                mask_x: 0,
+            };
+            for c in mask_str.chars() {
+                if !matches!(c, '0' | '1' | 'X') {
+                    return Err(util::Error::UnknownChar(c));
+                }
+                mask.mask_0 = (mask.mask_0 << 1) | ((c == '0') as u64);
+                mask.mask_1 = (mask.mask_1 << 1) | ((c == '1') as u64);
+                mask.mask_x = (mask.mask_x << 1) | ((c == 'X') as u64);
+            }
+
+            Ok(Command::Mask(mask))
+        } else if let Some(memset) = s.strip_prefix("mem[") {
+            let end = memset
+                .find("] = ")
+                .ok_or_else(|| util::Error::InvalidValue(s.to_owned()))?;
+            let location = memset[..end].parse::<u64>()?;
+            let value = memset[end + 4..].parse::<u64>()?;
+
+            let too_wide = |x: u64| width < 64 && x >> width != 0;
+            if too_wide(location) || too_wide(value) {
+                return Err(util::Error::InvalidValue(format!(
+                    "{} does not fit in {} bits",
+                    s, width
+                )));
+            }
 
             Ok(Command::Memset { location, value })
         } else {
@@ -50,10 +80,223 @@ impl std::str::FromStr for Command {
     }
 }
 
+impl std::str::FromStr for Command {
+    type Err = util::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s, DEFAULT_WIDTH)
+    }
+}
+
+// A set of addresses, where the floating bits take every possible value
+// and the remaining bits are given by fixed.  Floating bits are always
+// zero in fixed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct AddressPattern {
+    fixed: u64,
+    floating: u64,
+}
+
+impl AddressPattern {
+    fn new(fixed: u64, floating: u64) -> Self {
+        Self {
+            fixed: fixed & !floating,
+            floating,
+        }
+    }
+
+    fn num_addresses(&self) -> u128 {
+        1u128 << self.floating.count_ones()
+    }
+
+    fn addresses(&self) -> impl Iterator<Item = u64> {
+        // Steps through every subset of the floating bits.
+        let (fixed, floating) = (self.fixed, self.floating);
+        std::iter::successors(Some(0u64), move |&subset| {
+            if subset == floating {
+                None
+            } else {
+                Some(subset.wrapping_sub(floating) & floating)
+            }
+        })
+        .map(move |subset| fixed | subset)
+    }
+
+    fn contains(&self, address: u64) -> bool {
+        (address ^ self.fixed) & !self.floating == 0
+    }
+
+    // The addresses in self but not in other, as disjoint patterns.  Each
+    // piece pins one more of the bits that float in self but are fixed
+    // in other, taking the value that other does not have.
+    fn subtract(&self, other: &Self) -> Vec<Self> {
+        if self.intersection(other).is_none() {
+            return vec![*self];
+        }
+
+        let mut pieces = Vec::new();
+        let mut remaining = *self;
+        let mut split_bits = self.floating & !other.floating;
+        while split_bits != 0 {
+            let bit = split_bits & split_bits.wrapping_neg();
+            split_bits &= split_bits - 1;
+
+            let floating = remaining.floating & !bit;
+            pieces.push(Self::new(
+                remaining.fixed | (!other.fixed & bit),
+                floating,
+            ));
+            remaining =
+                Self::new(remaining.fixed | (other.fixed & bit), floating);
+        }
+        pieces
+    }
+
+    // The addresses in both patterns, or None if they have none in common.
+    fn intersection(&self, other: &Self) -> Option<Self> {
+        let both_fixed = !self.floating & !other.floating;
+        if (self.fixed ^ other.fixed) & both_fixed != 0 {
+            return None;
+        }
+        Some(Self::new(
+            self.fixed | other.fixed,
+            self.floating & other.floating,
+        ))
+    }
+}
+
+// Calls visit on disjoint pieces that together hold the addresses in the
+// pattern that no later pattern writes to, stopping as soon as visit
+// returns false.  Returns whether every piece was visited.
+//
+// Each step removes the largest overlapping write, leaving one piece per
+// bit that it fixes.  Counting a union of patterns is hard in general,
+// so this can take time exponential in the number of overlapping
+// writes, but only the one pattern is split and nothing is stored.
+fn visit_surviving<F>(
+    pattern: &AddressPattern,
+    later: &[AddressPattern],
+    visit: &mut F,
+) -> bool
+where
+    F: FnMut(&AddressPattern) -> bool,
+{
+    let mut overlapping = Vec::new();
+    for other in later {
+        if let Some(common) = pattern.intersection(other) {
+            if common == *pattern {
+                return true;
+            }
+            overlapping.push(common);
+        }
+    }
+
+    let largest = overlapping
+        .iter()
+        .enumerate()
+        .max_by_key(|(_i, common)| common.floating.count_ones())
+        .map(|(i, _common)| i);
+    match largest {
+        Some(i) => {
+            let largest = overlapping.swap_remove(i);
+            pattern
+                .subtract(&largest)
+                .iter()
+                .all(|piece| visit_surviving(piece, &overlapping, visit))
+        }
+        None => visit(pattern),
+    }
+}
+
+// The number of addresses in the pattern that no later pattern writes to.
+fn num_surviving(pattern: &AddressPattern, later: &[AddressPattern]) -> u128 {
+    let mut total = 0;
+    visit_surviving(pattern, later, &mut |piece| {
+        total += piece.num_addresses();
+        true
+    });
+    total
+}
+
+// Whether the later patterns write to every address in the pattern.
+fn is_covered(pattern: &AddressPattern, later: &[AddressPattern]) -> bool {
+    visit_surviving(pattern, later, &mut |_piece| false)
+}
+
+// Memory written through floating addresses.  Writes are kept as address
+// patterns, and each is only counted for the addresses that no later
+// write covers, so no pattern is expanded into addresses.
+#[derive(Debug, Default)]
+struct FloatingMemory {
+    patterns: Vec<AddressPattern>,
+    values: Vec<u64>,
+}
+
+impl FloatingMemory {
+    fn write(&mut self, pattern: AddressPattern, value: u64) {
+        self.patterns.push(pattern);
+        self.values.push(value);
+    }
+
+    fn sum(&self) -> u128 {
+        self.patterns
+            .iter()
+            .zip(self.values.iter())
+            .enumerate()
+            .map(|(i, (pattern, value))| {
+                num_surviving(pattern, &self.patterns[i + 1..])
+                    * (*value as u128)
+            })
+            .sum()
+    }
+
+    // Every address written to and its final value, or None if there
+    // are more than limit of them.
+    fn expand(&self, limit: u128) -> Option<HashMap<u64, u64>> {
+        let mut mem = HashMap::new();
+        let mut num_addresses = 0;
+        for (i, (pattern, value)) in
+            self.patterns.iter().zip(self.values.iter()).enumerate()
+        {
+            let within_limit = visit_surviving(
+                pattern,
+                &self.patterns[i + 1..],
+                &mut |piece| {
+                    num_addresses += piece.num_addresses();
+                    if num_addresses > limit {
+                        return false;
+                    }
+                    mem.extend(
+                        piece.addresses().map(|address| (address, *value)),
+                    );
+                    true
+                },
+            );
+            if !within_limit {
+                return None;
+            }
+        }
+        Some(mem)
+    }
+}
+
+// A memset command as executed, with the addresses it wrote to.
+#[derive(Debug, Copy, Clone)]
+struct Write {
+    line: usize,
+    mask: Mask,
+    location: u64,
+    value: u64,
+    addresses: AddressPattern,
+}
+
 #[derive(Debug)]
 struct DecoderState {
     mask: Mask,
     mem: HashMap<u64, u64>,
+    floating_mem: FloatingMemory,
+    line: usize,
+    history: Vec<Write>,
 }
 
 impl DecoderState {
@@ -65,16 +308,51 @@ impl DecoderState {
                 mask_x: 0u64,
             },
             mem: HashMap::new(),
+            floating_mem: FloatingMemory::default(),
+            line: 0,
+            history: Vec::new(),
         }
     }
 
+    fn record(&mut self, location: u64, value: u64, addresses: AddressPattern) {
+        self.history.push(Write {
+            line: self.line,
+            mask: self.mask,
+            location,
+            value,
+            addresses,
+        });
+    }
+
+    // Writes whose every address was written again by a later command.
+    fn overwritten(&self) -> Vec<&Write> {
+        let patterns: Vec<_> =
+            self.history.iter().map(|write| write.addresses).collect();
+        self.history
+            .iter()
+            .enumerate()
+            .filter(|(i, write)| {
+                is_covered(&write.addresses, &patterns[i + 1..])
+            })
+            .map(|(_i, write)| write)
+            .collect()
+    }
+
+    fn writes_to(&self, address: u64) -> impl Iterator<Item = &Write> {
+        self.history
+            .iter()
+            .filter(move |write| write.addresses.contains(address))
+    }
+
     fn apply_part1(&mut self, c: Command) {
+        self.line += 1;
         match c {
             Command::Mask(mask) => {
                 self.mask = mask;
             }
 
             Command::Memset { location, value } => {
+                self.record(location, value, AddressPattern::new(location, 0));
                 self.mem.insert(
                     location,
                     (value & (!self.mask.mask_0)) | self.mask.mask_1,
@@ -84,53 +362,125 @@ impl DecoderState {
     }
 
     fn apply_part2(&mut self, c: Command) {
+        self.line += 1;
         match c {
             Command::Mask(mask) => {
                 self.mask = mask;
             }
 
             Command::Memset { location, value } => {
-                let location =
-                    (location | self.mask.mask_1) & !self.mask.mask_x;
-
-                let floating_bits: Vec<_> = (0..36)
-                    .filter(|b| self.mask.mask_x & (1u64 << b) > 0)
-                    .collect();
-
-                (0..=floating_bits.len())
-                    .map(|len| floating_bits.iter().combinations(len))
-                    .flatten()
-                    .for_each(|bitset| {
-                        let location_mod = bitset
-                            .iter()
-                            .fold(location, |acc, &bit| acc | (1u64 << bit));
-                        self.mem.insert(location_mod, value);
-                    })
+                let pattern = AddressPattern::new(
+                    location | self.mask.mask_1,
+                    self.mask.mask_x,
+                );
+                self.record(location, value, pattern);
+                self.floating_mem.write(pattern, value);
             }
         }
     }
 }
 
+fn dump(mem: &HashMap<u64, u64>, width: u32) {
+    let hex_width = width.div_ceil(4) as usize;
+    mem.iter().sorted().for_each(|(address, value)| {
+        println!(
+            "\t{:#0hex$x} {:0bin$b} = {}",
+            address,
+            address,
+            value,
+            hex = hex_width + 2,
+            bin = width as usize
+        )
+    });
+}
+
+fn report(
+    state: &DecoderState,
+    args: &[String],
+    width: u32,
+) -> Result<(), util::Error> {
+    let describe = |write: &Write| {
+        format!(
+            "line {}: mem[{}] = {} with mask {}",
+            write.line,
+            write.location,
+            write.value,
+            write.mask.describe(width)
+        )
+    };
+
+    if util::has_flag(args, "overwritten") {
+        println!("Writes that were overwritten:");
+        state
+            .overwritten()
+            .iter()
+            .for_each(|write| println!("\t{}", describe(write)));
+    }
+
+    if let Some(address) = util::arg_value(args, "address") {
+        let address = address.parse::<u64>()?;
+        println!("Writes to address {}:", address);
+        state
+            .writes_to(address)
+            .for_each(|write| println!("\t{}", describe(write)));
+    }
+
+    Ok(())
+}
+
 fn main() -> Result<(), util::Error> {
     let args: Vec<String> = std::env::args().collect();
     let filename = &args[1];
 
+    let width = util::arg_value(&args, "width")
+        .map(|val| val.parse::<u32>())
+        .transpose()?
+        .unwrap_or(DEFAULT_WIDTH);
+    if width == 0 || width > 64 {
+        return Err(util::Error::InvalidValue(format!(
+            "Word width {} is not between 1 and 64",
+            width
+        )));
+    }
+
     let commands = std::fs::read_to_string(filename)?
         .lines()
-        .map(|s| s.parse::<Command>())
+        .enumerate()
+        .map(|(i, s)| {
+            Command::parse(s, width)
+                .map_err(|e| util::Error::AtLine(i + 1, Box::new(e)))
+        })
         .collect::<Result<Vec<_>, _>>()?;
 
     let mut state = DecoderState::new();
     commands.iter().for_each(|c| state.apply_part1(*c));
 
-    let sum = state.mem.iter().map(|(_k, v)| v).sum::<u64>();
+    let sum = state.mem.values().map(|v| *v as u128).sum::<u128>();
     println!("Part a, sum = {}", sum);
+    if util::has_flag(&args, "dump") {
+        dump(&state.mem, width);
+    }
+    report(&state, &args, width)?;
 
     let mut state = DecoderState::new();
     commands.iter().for_each(|&c| state.apply_part2(c));
 
-    let sum = state.mem.iter().map(|(_k, v)| v).sum::<u64>();
+    let sum = state.floating_mem.sum();
     println!("Part b, sum = {}", sum);
+    if util::has_flag(&args, "dump") {
+        let limit = util::arg_value(&args, "dump-limit")
+            .map(|val| val.parse::<u128>())
+            .transpose()?
+            .unwrap_or(1 << 16);
+        match state.floating_mem.expand(limit) {
+            Some(mem) => dump(&mem, width),
+            None => println!(
+                "\tMore than {} addresses written, use --dump-limit to raise",
+                limit
+            ),
+        }
+    }
+    report(&state, &args, width)?;
 
     Ok(())
 }
@@ -1,43 +1,188 @@
 use std::collections::HashMap;
+use std::io::Write;
 
+// Marks a number in MemoryGame::recent whose turn is stored in the
+// spill map instead, because the turn does not fit in a u32.
+const SPILLED: u32 = u32::MAX;
+
+// The largest flat array to allocate; larger numbers use the spill map.
+const MAX_CAPACITY: u64 = 1 << 27;
+
+// The memory game, as an iterator over the numbers spoken on each turn,
+// beginning with the starting numbers.
 #[derive(Debug)]
-struct State {
-    turn_num: i32,
-    current_num: i32,
-    when_last_used: HashMap<i32, i32>,
+struct MemoryGame {
+    starting: Vec<u64>,
+    turn_num: u64,
+    current_num: u64,
+    // The turn on which each number below the capacity was last
+    // spoken, or zero if it has not been spoken yet.
+    recent: Vec<u32>,
+    spilled: HashMap<u64, u64>,
 }
 
-impl State {
-    fn new() -> Self {
+impl MemoryGame {
+    // Numbers below capacity are tracked in a flat array, which is
+    // fastest when capacity is at least the number of turns played.
+    fn new(starting: &[u64], capacity: usize) -> Self {
         Self {
+            starting: starting.to_vec(),
             turn_num: 0,
             current_num: 0,
-            when_last_used: HashMap::new(),
+            recent: vec![0; capacity],
+            spilled: HashMap::new(),
+        }
+    }
+
+    fn last_used(&self, x: u64) -> Option<u64> {
+        match self.recent.get(x as usize) {
+            Some(0) => None,
+            Some(&SPILLED) | None => self.spilled.get(&x).copied(),
+            Some(&turn) => Some(turn as u64),
         }
     }
 
-    fn add_num(&mut self, x: i32) {
-        self.when_last_used.insert(self.current_num, self.turn_num);
-        self.current_num = x;
+    fn set_last_used(&mut self, x: u64, turn: u64) {
+        match self.recent.get_mut(x as usize) {
+            Some(recent) if turn < SPILLED as u64 => *recent = turn as u32,
+            Some(recent) => {
+                *recent = SPILLED;
+                self.spilled.insert(x, turn);
+            }
+            None => {
+                self.spilled.insert(x, turn);
+            }
+        }
+    }
+}
+
+impl Iterator for MemoryGame {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        let next_num = match self.starting.get(self.turn_num as usize) {
+            Some(&x) => x,
+            None => self
+                .last_used(self.current_num)
+                .map_or(0, |turn| self.turn_num - turn),
+        };
+
+        if self.turn_num > 0 {
+            self.set_last_used(self.current_num, self.turn_num);
+        }
+        self.current_num = next_num;
         self.turn_num += 1;
+
+        Some(next_num)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct NumberStats {
+    count: u64,
+    first_turn: u64,
+    last_turn: u64,
+    largest_gap: u64,
+}
+
+// Statistics over the first turns of a game, for comparing starting
+// sequences.
+#[derive(Debug)]
+struct SequenceStats {
+    numbers: HashMap<u64, NumberStats>,
+    // (turn, zeros spoken so far, zeros spoken since the previous sample)
+    zeros: Vec<(u64, u64, u64)>,
+    sample_interval: u64,
+    last_turn: u64,
+    num_zeros: u64,
+    sampled_zeros: u64,
+}
+
+impl SequenceStats {
+    fn new(sample_interval: u64) -> Self {
+        Self {
+            numbers: HashMap::new(),
+            zeros: Vec::new(),
+            sample_interval,
+            last_turn: 0,
+            num_zeros: 0,
+            sampled_zeros: 0,
+        }
+    }
+
+    fn record(&mut self, turn: u64, x: u64) {
+        self.numbers
+            .entry(x)
+            .and_modify(|stats| {
+                stats.count += 1;
+                stats.largest_gap =
+                    stats.largest_gap.max(turn - stats.last_turn);
+                stats.last_turn = turn;
+            })
+            .or_insert(NumberStats {
+                count: 1,
+                first_turn: turn,
+                last_turn: turn,
+                largest_gap: 0,
+            });
+
+        if x == 0 {
+            self.num_zeros += 1;
+        }
+        self.last_turn = turn;
+        if turn.is_multiple_of(self.sample_interval) {
+            self.sample();
+        }
+    }
+
+    fn sample(&mut self) {
+        self.zeros.push((
+            self.last_turn,
+            self.num_zeros,
+            self.num_zeros - self.sampled_zeros,
+        ));
+        self.sampled_zeros = self.num_zeros;
     }
 
-    fn next_num(&self) -> i32 {
-        let prev_turn_used = self.when_last_used.get(&self.current_num);
-        match prev_turn_used {
-            Some(x) => self.turn_num - x,
-            None => 0,
+    // Samples the zero density at the last turn, if not already done.
+    fn finish(&mut self) {
+        if self.zeros.last().map(|(turn, _, _)| *turn) != Some(self.last_turn) {
+            self.sample();
         }
     }
 
-    fn iter(&mut self) {
-        self.add_num(self.next_num());
+    fn write_numbers_csv(
+        &self,
+        writer: &mut impl Write,
+    ) -> std::io::Result<()> {
+        writeln!(writer, "number,count,first_turn,largest_gap")?;
+        let mut numbers: Vec<_> = self.numbers.iter().collect();
+        numbers.sort_unstable_by_key(|(x, _stats)| **x);
+        for (x, stats) in numbers {
+            writeln!(
+                writer,
+                "{},{},{},{}",
+                x, stats.count, stats.first_turn, stats.largest_gap
+            )?;
+        }
+        Ok(())
     }
 
-    fn loop_until(&mut self, last_iter: i32) {
-        while self.turn_num < last_iter {
-            self.iter();
+    fn write_zeros_csv(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        writeln!(writer, "turn,zeros,density,window_density")?;
+        let mut prev_turn = 0;
+        for &(turn, num_zeros, window_zeros) in &self.zeros {
+            writeln!(
+                writer,
+                "{},{},{},{}",
+                turn,
+                num_zeros,
+                num_zeros as f64 / turn as f64,
+                window_zeros as f64 / (turn - prev_turn) as f64
+            )?;
+            prev_turn = turn;
         }
+        Ok(())
     }
 }
 
@@ -47,17 +192,62 @@ fn main() -> Result<(), util::Error> {
 
     let starter = std::fs::read_to_string(filename)?
         .split(',')
-        .map(|s| s.trim().parse::<i32>())
+        .map(|s| s.trim().parse::<u64>())
         .collect::<Result<Vec<_>, _>>()?;
 
-    let mut state = State::new();
-    starter.iter().for_each(|&x| state.add_num(x));
+    let last_turn = util::arg_value(&args, "turns")
+        .map(|val| val.parse::<u64>())
+        .transpose()?
+        .unwrap_or(30000000);
+
+    let mut stream = util::arg_value(&args, "stream")
+        .map(std::fs::File::create)
+        .transpose()?
+        .map(std::io::BufWriter::new);
+
+    let stats_prefix = util::arg_value(&args, "stats");
+    let mut stats = match stats_prefix {
+        Some(_) => Some(SequenceStats::new(
+            util::arg_value(&args, "sample")
+                .map(|val| val.parse::<u64>())
+                .transpose()?
+                .unwrap_or(1000)
+                .max(1),
+        )),
+        None => None,
+    };
 
-    state.loop_until(2020);
-    println!("Iter 2020 = {}", state.current_num);
+    // Play a single game, feeding every consumer as it goes.
+    let game = MemoryGame::new(&starter, last_turn.min(MAX_CAPACITY) as usize);
+    for (turn, x) in (1..=last_turn.max(2020)).zip(game) {
+        if turn == 2020 {
+            println!("Iter 2020 = {}", x);
+        }
+        if turn > last_turn {
+            continue;
+        }
+        if turn == last_turn && turn != 2020 {
+            println!("Iter {} = {}", last_turn, x);
+        }
+        if let Some(writer) = &mut stream {
+            writeln!(writer, "{}", x)?;
+        }
+        if let Some(stats) = &mut stats {
+            stats.record(turn, x);
+        }
+    }
 
-    state.loop_until(30000000);
-    println!("Iter 30000000 = {}", state.current_num);
+    if let (Some(prefix), Some(stats)) = (stats_prefix, &mut stats) {
+        stats.finish();
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(
+            format!("{}_numbers.csv", prefix),
+        )?);
+        stats.write_numbers_csv(&mut writer)?;
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(
+            format!("{}_zeros.csv", prefix),
+        )?);
+        stats.write_zeros_csv(&mut writer)?;
+    }
 
     Ok(())
 }
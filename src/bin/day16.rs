@@ -1,4 +1,4 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 
 use itertools::Itertools;
 use regex::Regex;
@@ -67,6 +67,192 @@ fn parse_text(text: &str) -> (Vec<Rule>, Vec<Ticket>) {
     (rules, tickets)
 }
 
+// The column of each ticket field, by field name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FieldMap {
+    indices: HashMap<String, usize>,
+}
+
+impl FieldMap {
+    fn get(&self, name: &str) -> Option<usize> {
+        self.indices.get(name).copied()
+    }
+}
+
+impl std::fmt::Display for FieldMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let fields = self
+            .indices
+            .iter()
+            .sorted_by_key(|(_name, index)| **index)
+            .map(|(name, index)| format!("{}: {}", name, index))
+            .join(", ");
+        write!(f, "{{{}}}", fields)
+    }
+}
+
+#[derive(Debug)]
+enum Resolution {
+    Unique(FieldMap),
+    // Holds the valid assignments found, up to the search limit, and
+    // whether more were left out.
+    Ambiguous {
+        field_maps: Vec<FieldMap>,
+        truncated: bool,
+    },
+    Impossible,
+}
+
+// Assigns each rule to a distinct column that all of its values match,
+// as a bipartite matching between rules and columns.
+#[derive(Debug)]
+struct FieldResolver<'a> {
+    rules: &'a [Rule],
+    candidates: Vec<Vec<usize>>,
+    num_columns: usize,
+}
+
+impl<'a> FieldResolver<'a> {
+    fn new(rules: &'a [Rule], tickets: &[&Ticket]) -> Self {
+        let num_columns =
+            tickets.iter().map(|t| t.values.len()).min().unwrap_or(0);
+        let candidates = rules
+            .iter()
+            .map(|rule| {
+                (0..num_columns)
+                    .filter(|&index| {
+                        tickets.iter().all(|t| rule.matches(t.values[index]))
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            rules,
+            candidates,
+            num_columns,
+        }
+    }
+
+    // Finds a column for the rule, moving previously matched rules to
+    // other columns along an augmenting path if needed.
+    fn augment(
+        &self,
+        rule: usize,
+        taken: &[bool],
+        owner: &mut Vec<Option<usize>>,
+        visited: &mut Vec<bool>,
+    ) -> bool {
+        for &index in &self.candidates[rule] {
+            if taken[index] || visited[index] {
+                continue;
+            }
+            visited[index] = true;
+            let moved = match owner[index] {
+                Some(other) => self.augment(other, taken, owner, visited),
+                None => true,
+            };
+            if moved {
+                owner[index] = Some(rule);
+                return true;
+            }
+        }
+        false
+    }
+
+    // Whether every one of the rules can be given a distinct column,
+    // without using the taken columns.
+    fn can_match(&self, rules: &[usize], taken: &[bool]) -> bool {
+        let mut owner = vec![None; self.num_columns];
+        rules.iter().all(|&rule| {
+            let mut visited = vec![false; self.num_columns];
+            self.augment(rule, taken, &mut owner, &mut visited)
+        })
+    }
+
+    // Depth-first search over assignments, always branching on the rule
+    // with the fewest free columns.  Branches that cannot be completed
+    // are cut off by the matching check, so every leaf is a solution.
+    fn search(
+        &self,
+        assignment: &mut Vec<Option<usize>>,
+        taken: &mut Vec<bool>,
+        limit: usize,
+        solutions: &mut Vec<Vec<usize>>,
+    ) {
+        if solutions.len() >= limit {
+            return;
+        }
+
+        let unassigned: Vec<usize> = (0..self.rules.len())
+            .filter(|&rule| assignment[rule].is_none())
+            .collect();
+        if !self.can_match(&unassigned, taken) {
+            return;
+        }
+
+        let free = |rule: usize| {
+            self.candidates[rule]
+                .iter()
+                .copied()
+                .filter(|&index| !taken[index])
+                .collect::<Vec<_>>()
+        };
+        let rule = match unassigned.iter().min_by_key(|&&r| free(r).len()) {
+            Some(&rule) => rule,
+            None => {
+                solutions.push(assignment.iter().flatten().copied().collect());
+                return;
+            }
+        };
+
+        for index in free(rule) {
+            assignment[rule] = Some(index);
+            taken[index] = true;
+            self.search(assignment, taken, limit, solutions);
+            taken[index] = false;
+            assignment[rule] = None;
+        }
+    }
+
+    fn resolve(&self, limit: usize) -> Resolution {
+        let mut solutions = Vec::new();
+        self.search(
+            &mut vec![None; self.rules.len()],
+            &mut vec![false; self.num_columns],
+            // One past the limit shows whether any were left out, and
+            // at least two are needed to recognize ambiguity.
+            limit.max(1) + 1,
+            &mut solutions,
+        );
+
+        let mut field_maps: Vec<_> = solutions
+            .iter()
+            .map(|indices| FieldMap {
+                indices: self
+                    .rules
+                    .iter()
+                    .map(|rule| rule.name.to_owned())
+                    .zip(indices.iter().copied())
+                    .collect(),
+            })
+            .collect();
+
+        match field_maps.len() {
+            0 => Resolution::Impossible,
+            1 => Resolution::Unique(field_maps.remove(0)),
+            _ => {
+                let truncated = field_maps.len() > limit;
+                field_maps.truncate(limit);
+                Resolution::Ambiguous {
+                    field_maps,
+                    truncated,
+                }
+            }
+        }
+    }
+}
+
 fn main() -> Result<(), util::Error> {
     let args: Vec<String> = std::env::args().collect();
     let filename = &args[1];
@@ -92,57 +278,44 @@ fn main() -> Result<(), util::Error> {
         })
         .collect();
 
-    let rule_map: Vec<_> = rules
-        .iter()
-        .cartesian_product(0usize..rules.len())
-        .filter(|&(rule, index)| {
-            tickets.iter().all(|t| rule.matches(t.values[index]))
-        })
-        .map(|(rule, index)| (rule.name.to_owned(), index))
-        .collect::<Vec<_>>();
-
-    let mut rule_possibilities: HashMap<String, HashSet<usize>> =
-        HashMap::new();
-    rule_map.iter().for_each(|(rule, index)| {
-        rule_possibilities
-            .entry(rule.to_string())
-            .or_insert_with(HashSet::new)
-            .insert(*index);
-    });
-
-    // If a rule is known, then it can't be used for any other fields.
-    // Identify known fields, then remove them as possibilities from
-    // other fields.
-    loop {
-        let known_indices: Vec<usize> = rule_possibilities
-            .iter()
-            .filter(|(_rule, indices)| indices.len() == 1)
-            .map(|(_rule, indices)| *indices.iter().next().unwrap())
-            .collect();
-
-        let change_made = known_indices.iter().any(|known_index| {
-            rule_possibilities.iter_mut().any(|(_rule, indices)| {
-                if indices.len() > 1 {
-                    indices.remove(known_index)
-                } else {
-                    false
-                }
-            })
-        });
+    let limit = util::arg_value(&args, "max-assignments")
+        .map(|val| val.parse::<usize>())
+        .transpose()?
+        .unwrap_or(100);
+    if limit == 0 {
+        return Err(util::Error::InvalidValue(
+            "--max-assignments must be at least 1".to_string(),
+        ));
+    }
 
-        if !change_made {
-            break;
+    let field_map = match FieldResolver::new(&rules, &tickets).resolve(limit) {
+        Resolution::Unique(field_map) => field_map,
+        Resolution::Ambiguous {
+            field_maps,
+            truncated,
+        } => {
+            println!("Rule map is ambiguous, valid assignments:");
+            field_maps
+                .iter()
+                .for_each(|field_map| println!("\t{}", field_map));
+            if truncated {
+                println!("\t(stopped after {})", limit);
+            }
+            return Ok(());
         }
-    }
+        Resolution::Impossible => {
+            println!("No assignment of rules to fields is valid");
+            return Ok(());
+        }
+    };
 
-    println!("Rule map = {:?}", rule_possibilities);
+    println!("Rule map = {}", field_map);
 
-    let departure_product = rule_possibilities
+    let departure_product = rules
         .iter()
-        .filter(|(rule, _indices)| rule.starts_with("departure"))
-        .map(|(_rule, indices)| {
-            tickets[0].values[*indices.iter().next().unwrap()] as i64
-        })
+        .filter(|rule| rule.name.starts_with("departure"))
+        .filter_map(|rule| field_map.get(&rule.name))
+        .map(|index| tickets[0].values[index] as i64)
         .product::<i64>();
 
     println!("Part b, departure product = {}", departure_product);
@@ -86,6 +86,51 @@ impl GameOfLifeLocation for Cube4D {
     }
 }
 
+impl GameOfLife<Cube3D> {
+    // The smallest and largest coordinates of any active cube.
+    fn bounds(&self) -> Option<([i32; 3], [i32; 3])> {
+        util::bounding_box(self.active.iter().map(|cube| cube.pos))
+    }
+
+    // Draws each z-slice within the bounds, with the slices side by
+    // side in the image.
+    fn render(&self, min: [i32; 3], max: [i32; 3]) -> (String, util::Frame) {
+        let width = (max[0] - min[0] + 1) as usize;
+        let height = (max[1] - min[1] + 1) as usize;
+        let num_slices = (max[2] - min[2] + 1) as usize;
+
+        let mut text = String::new();
+        let mut frame = util::Frame::new(
+            (width + 1) * num_slices - 1,
+            height,
+            [128, 128, 128],
+        );
+        for (slice, z) in (min[2]..=max[2]).enumerate() {
+            text += &format!("z={}\n", z);
+            for (row, y) in (min[1]..=max[1]).enumerate() {
+                for (col, x) in (min[0]..=max[0]).enumerate() {
+                    let is_active =
+                        self.active.contains(&Cube3D { pos: [x, y, z] });
+                    text.push(if is_active { '#' } else { '.' });
+                    frame.set(
+                        slice * (width + 1) + col,
+                        row,
+                        if is_active {
+                            [255, 255, 255]
+                        } else {
+                            [0, 0, 0]
+                        },
+                    );
+                }
+                text.push('\n');
+            }
+            text.push('\n');
+        }
+
+        (text, frame)
+    }
+}
+
 fn main() -> Result<(), util::Error> {
     let args: Vec<String> = std::env::args().collect();
     let filename = &args[1];
@@ -99,18 +144,27 @@ fn main() -> Result<(), util::Error> {
         .map(|(x, y, _c)| (x as i32, y as i32))
         .collect();
 
-    let mut game = GameOfLife {
+    let mut generations = vec![GameOfLife {
         active: starter
             .iter()
             .map(|(x, y)| Cube3D { pos: [*x, *y, 0] })
             .collect(),
-    };
+    }];
     for _ in 0..6 {
-        game = game.next();
+        generations.push(generations.last().unwrap().next());
     }
+    let game = generations.last().unwrap();
 
     println!("Part a, num active: {}", game.active.len());
 
+    if let Some(frame_output) = util::FrameOutput::from_args(&args)? {
+        frame_output.write_animation(
+            &generations,
+            |game| game.bounds(),
+            |game, min, max| game.render(min, max),
+        )?;
+    }
+
     let mut game = GameOfLife {
         active: starter
             .iter()
@@ -135,6 +135,56 @@ impl GameOfLife {
     }
 }
 
+impl TileLoc {
+    // Position in a grid where each row is offset by half a tile from
+    // the next, as (column, row) with two columns per tile.
+    fn as_doubled(&self) -> (i32, i32) {
+        (2 * self.x + self.z, -self.z)
+    }
+}
+
+impl GameOfLife {
+    fn bounds(&self) -> Option<([i32; 2], [i32; 2])> {
+        util::bounding_box(self.active.iter().map(|tile| {
+            let (col, row) = tile.as_doubled();
+            [col, row]
+        }))
+    }
+
+    // Draws the tiles within the bounds, given as doubled
+    // coordinates.  Each tile is two pixels wide in the image.
+    fn render(&self, min: [i32; 2], max: [i32; 2]) -> (String, util::Frame) {
+        let width = (max[0] - min[0] + 1) as usize;
+        let height = (max[1] - min[1] + 1) as usize;
+
+        let black = self
+            .active
+            .iter()
+            .map(|tile| tile.as_doubled())
+            .collect::<HashSet<_>>();
+
+        let mut text = String::new();
+        let mut frame = util::Frame::new(width + 1, height, [64, 64, 64]);
+        for (y, row) in (min[1]..=max[1]).enumerate() {
+            for (x, col) in (min[0]..=max[0]).enumerate() {
+                // Only every other column holds a tile center.
+                if (col + row).rem_euclid(2) != 0 {
+                    text.push(' ');
+                    continue;
+                }
+                let is_black = black.contains(&(col, row));
+                text.push(if is_black { '#' } else { '.' });
+                let color = if is_black { [0, 0, 0] } else { [255, 255, 255] };
+                frame.set(x, y, color);
+                frame.set(x + 1, y, color);
+            }
+            text.push('\n');
+        }
+
+        (text, frame)
+    }
+}
+
 fn tile_location(directions: &[Direction]) -> TileLoc {
     directions
         .iter()
@@ -166,13 +216,22 @@ fn main() -> Result<(), util::Error> {
 
     println!("Part 1, num black tiles: {}", active_tiles.len());
 
-    let mut game = GameOfLife {
+    let mut generations = vec![GameOfLife {
         active: active_tiles,
-    };
+    }];
     for _ in 0..100 {
-        game = game.next_iter();
+        generations.push(generations.last().unwrap().next_iter());
     }
+    let game = generations.last().unwrap();
     println!("Part 2, after 100 days: {}", game.active.len());
 
+    if let Some(frame_output) = util::FrameOutput::from_args(&args)? {
+        frame_output.write_animation(
+            &generations,
+            |game| game.bounds(),
+            |game, min, max| game.render(min, max),
+        )?;
+    }
+
     Ok(())
 }
@@ -1,6 +1,7 @@
 use std::fs::File;
-use std::io::{self, BufRead, Lines};
+use std::io::{self, BufRead, Lines, Write};
 use std::path::Path;
+use std::time::Duration;
 
 pub fn parse_file<T, E: 'static, P>(
     filename: P,
@@ -30,6 +31,231 @@ where
     Ok(io::BufReader::new(file).lines())
 }
 
+// Returns the value following a "--name" argument, or given as
+// "--name=value".
+pub fn arg_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    let flag = format!("--{}", name);
+    let prefix = format!("--{}=", name);
+    args.iter().enumerate().find_map(|(i, arg)| {
+        if arg == &flag {
+            args.get(i + 1).map(|s| s.as_str())
+        } else {
+            arg.strip_prefix(&prefix)
+        }
+    })
+}
+
+pub fn has_flag(args: &[String], name: &str) -> bool {
+    let flag = format!("--{}", name);
+    args.iter().any(|arg| arg == &flag)
+}
+
+// The smallest and largest coordinate along each axis, or None if there
+// are no points.
+pub fn bounding_box<const N: usize>(
+    points: impl IntoIterator<Item = [i32; N]>,
+) -> Option<([i32; N], [i32; N])> {
+    let mut points = points.into_iter();
+    let first = points.next()?;
+    Some(points.fold((first, first), |(mut min, mut max), point| {
+        (0..N).for_each(|i| {
+            min[i] = min[i].min(point[i]);
+            max[i] = max[i].max(point[i]);
+        });
+        (min, max)
+    }))
+}
+
+// An RGB image of a single generation of an automaton.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<[u8; 3]>,
+}
+
+impl Frame {
+    pub fn new(width: usize, height: usize, background: [u8; 3]) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![background; width * height],
+        }
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: [u8; 3]) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    fn scaled(&self, scale: usize) -> Self {
+        let mut output =
+            Frame::new(self.width * scale, self.height * scale, [0, 0, 0]);
+        for y in 0..output.height {
+            for x in 0..output.width {
+                output.set(
+                    x,
+                    y,
+                    self.pixels[(y / scale) * self.width + (x / scale)],
+                );
+            }
+        }
+        output
+    }
+
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut output =
+            format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        self.pixels
+            .iter()
+            .for_each(|pixel| output.extend_from_slice(pixel));
+        output
+    }
+
+    pub fn to_pgm(&self) -> Vec<u8> {
+        let mut output =
+            format!("P5\n{} {}\n255\n", self.width, self.height).into_bytes();
+        self.pixels.iter().for_each(|[r, g, b]| {
+            let luma =
+                (299 * *r as u32 + 587 * *g as u32 + 114 * *b as u32) / 1000;
+            output.push(luma as u8);
+        });
+        output
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Ppm,
+    Pgm,
+}
+
+// Where to send each generation of an animated automaton.
+#[derive(Debug, Clone)]
+pub enum FrameOutput {
+    // Redraw the terminal for each frame, pausing between them.
+    Terminal {
+        delay: Duration,
+    },
+    // Write numbered images, named prefix_0000.ppm and so on.
+    Images {
+        prefix: String,
+        format: ImageFormat,
+        scale: usize,
+    },
+}
+
+impl FrameOutput {
+    // Reads "--play [--delay MS]" or "--frames PREFIX [--format
+    // ppm|pgm] [--scale N]", returning None if neither was given.
+    pub fn from_args(args: &[String]) -> Result<Option<Self>, Error> {
+        let parse_or = |name: &str, default: u64| -> Result<u64, Error> {
+            Ok(match arg_value(args, name) {
+                Some(val) => val.parse::<u64>()?,
+                None => default,
+            })
+        };
+
+        if has_flag(args, "play") {
+            Ok(Some(FrameOutput::Terminal {
+                delay: Duration::from_millis(parse_or("delay", 100)?),
+            }))
+        } else if let Some(prefix) = arg_value(args, "frames") {
+            let format = match arg_value(args, "format") {
+                None | Some("ppm") => ImageFormat::Ppm,
+                Some("pgm") => ImageFormat::Pgm,
+                Some(format) => {
+                    return Err(Error::InvalidValue(format.to_string()))
+                }
+            };
+            Ok(Some(FrameOutput::Images {
+                prefix: prefix.to_string(),
+                format,
+                scale: parse_or("scale", 4)?.max(1) as usize,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // For animating several runs in one program without overwriting
+    // each other's images.
+    pub fn with_suffix(&self, suffix: &str) -> Self {
+        match self {
+            FrameOutput::Images {
+                prefix,
+                format,
+                scale,
+            } => FrameOutput::Images {
+                prefix: format!("{}_{}", prefix, suffix),
+                format: *format,
+                scale: *scale,
+            },
+            _ => self.clone(),
+        }
+    }
+
+    // Renders and writes every generation.  All generations are drawn
+    // within one box that holds each of their bounds, so that every
+    // image has the same size.
+    pub fn write_animation<G, const N: usize>(
+        &self,
+        generations: &[G],
+        bounds: impl Fn(&G) -> Option<([i32; N], [i32; N])>,
+        render: impl Fn(&G, [i32; N], [i32; N]) -> (String, Frame),
+    ) -> Result<(), Error> {
+        let corners = generations
+            .iter()
+            .filter_map(&bounds)
+            .flat_map(|(min, max)| vec![min, max]);
+        let (min, max) = match bounding_box(corners) {
+            Some(bounds) => bounds,
+            None => return Ok(()),
+        };
+        generations
+            .iter()
+            .enumerate()
+            .try_for_each(|(i, generation)| {
+                let (text, frame) = render(generation, min, max);
+                self.write(i, &text, &frame)
+            })
+    }
+
+    pub fn write(
+        &self,
+        index: usize,
+        text: &str,
+        frame: &Frame,
+    ) -> Result<(), Error> {
+        match self {
+            FrameOutput::Terminal { delay } => {
+                let stdout = io::stdout();
+                let mut handle = stdout.lock();
+                write!(handle, "\x1b[2J\x1b[H")?;
+                writeln!(handle, "Frame {}", index)?;
+                write!(handle, "{}", text)?;
+                handle.flush()?;
+                std::thread::sleep(*delay);
+            }
+            FrameOutput::Images {
+                prefix,
+                format,
+                scale,
+            } => {
+                let frame = frame.scaled(*scale);
+                let (extension, data) = match format {
+                    ImageFormat::Ppm => ("ppm", frame.to_ppm()),
+                    ImageFormat::Pgm => ("pgm", frame.to_pgm()),
+                };
+                std::fs::write(
+                    format!("{}_{:04}.{}", prefix, index, extension),
+                    data,
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
 pub fn gcd(a: i32, b: i32) -> i32 {
     if b == 0 {
         a
@@ -51,6 +277,8 @@ pub enum Error {
     Mismatch,
     GameFinished,
     GameNotFinished,
+    // An error in the given line of an input file, counting from 1.
+    AtLine(usize, Box<Error>),
 }
 
 impl From<std::io::Error> for Error {